@@ -0,0 +1,105 @@
+// How much flattening a wide commit into rows costs, as it was done (every row
+// copied into an owned FlatCommit and collected before any were written) against
+// how it's done now (rows borrowed from the commit and written as they're made).
+// Counts the allocations and the most memory in use at once with an allocator
+// of its own - run with cargo bench
+//
+use git_fetch_commits::{
+    commit::{Commit, FlatCommit},
+    diff::{ChangeStatus, FileChange},
+    sink::{NdjsonSink, OutputSink},
+    SCHEMA_VERSION,
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+const FILES_CHANGED: usize = 20_000;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        let in_use = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(in_use, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn wide_commit() -> Commit {
+    Commit {
+        schema_version: SCHEMA_VERSION,
+        record_kind: None,
+        id: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string(),
+        short_id: "4b825dc".to_string(),
+        tree_id: Some("4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string()),
+        repo_url: "https://example.com/monorepo.git".to_string(),
+        author_name: Some("Ada".to_string()),
+        author_email: Some("ada@example.com".to_string()),
+        message: "Reformat everything\n\nRan the formatter over the whole tree.\n".repeat(4),
+        parent_count: 1,
+        changes: (0..FILES_CHANGED)
+            .map(|n| FileChange {
+                path: format!("services/service-{}/src/module_{}.rs", n % 100, n),
+                status: ChangeStatus::Modified,
+                lines_added: 3,
+                lines_removed: 3,
+                hunks_modified: 1,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn measure(name: &str, run: impl FnOnce()) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let in_use = IN_USE.load(Ordering::Relaxed);
+    PEAK.store(in_use, Ordering::Relaxed);
+    let started = Instant::now();
+
+    run();
+
+    println!(
+        "  {:<28} {:>8} allocations, {:>8} KiB at most, {:>6.1} ms",
+        name,
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        (PEAK.load(Ordering::Relaxed) - in_use) / 1024,
+        started.elapsed().as_secs_f64() * 1000.0
+    );
+}
+
+fn main() {
+    let commit = wide_commit();
+    println!("Flat rows for a commit changing {} files", FILES_CHANGED);
+
+    measure("owned, collected first", || {
+        let rows: Vec<FlatCommit> = commit.flat().map(FlatCommit::from).collect();
+        let mut out = io::sink();
+        for row in rows {
+            let json = serde_json::to_string(&row).unwrap() + "\n";
+            out.write_all(json.as_bytes()).unwrap();
+        }
+    });
+
+    measure("borrowed, streamed", || {
+        let mut sink = NdjsonSink::new(io::sink(), false, false, false);
+        sink.write_commit(&commit).unwrap();
+    });
+}
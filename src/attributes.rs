@@ -0,0 +1,276 @@
+use git2::{AttrCheckFlags, Repository, Tree};
+use regex::Regex;
+use std::{cell::RefCell, collections::HashMap, path::Path, rc::Rc};
+
+// The value a path gets for one attribute, as in gitattributes(5): "name" sets it,
+// "-name" unsets it and "name=value" gives it a value. A path no line mentions (or
+// one reset with "!name") gets None
+//
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    Set,
+    Unset,
+    Value(String),
+}
+
+// Where the attributes of the paths in a diff come from. libgit2 only ever reads
+// them from the working directory (and index / HEAD), which a bare --mirror clone
+// doesn't have, and which would judge every historical commit by today's
+// .gitattributes. A commit's changes use the .gitattributes files in that commit's
+// own tree instead; the uncommitted WORKING changes use the working directory's
+//
+pub enum Attributes<'r> {
+    Tree(TreeAttributes<'r>),
+    Worktree(&'r Repository),
+}
+
+impl<'r> Attributes<'r> {
+    pub fn from_tree(repo: &'r Repository, tree: &Tree<'r>) -> Attributes<'r> {
+        Attributes::Tree(TreeAttributes {
+            repo,
+            tree: tree.clone(),
+            files: RefCell::new(HashMap::new()),
+        })
+    }
+
+    pub fn worktree(repo: &'r Repository) -> Attributes<'r> {
+        Attributes::Worktree(repo)
+    }
+
+    pub fn get(&self, path: &str, name: &str) -> Option<AttrValue> {
+        match self {
+            Attributes::Tree(tree) => tree.get(path, name),
+            Attributes::Worktree(repo) => {
+                let flags = AttrCheckFlags::default();
+                match git2::AttrValue::from_string(
+                    repo.get_attr(Path::new(path), name, flags).ok()?,
+                ) {
+                    git2::AttrValue::True => Some(AttrValue::Set),
+                    git2::AttrValue::False => Some(AttrValue::Unset),
+                    git2::AttrValue::String(value) => Some(AttrValue::Value(value.to_string())),
+                    git2::AttrValue::Bytes(value) => Some(AttrValue::Value(
+                        String::from_utf8_lossy(value).into_owned(),
+                    )),
+                    git2::AttrValue::Unspecified => None,
+                }
+            }
+        }
+    }
+}
+
+// One line of a .gitattributes file - a pattern and what it does to each of the
+// attributes it names (None for "!name")
+//
+struct Rule {
+    pattern: Regex,
+    attributes: Vec<(String, Option<AttrValue>)>,
+}
+
+pub struct TreeAttributes<'r> {
+    repo: &'r Repository,
+    tree: Tree<'r>,
+
+    // The rules of the .gitattributes in each directory looked at so far, keyed on
+    // the directory ("" for the top of the tree)
+    //
+    files: RefCell<HashMap<String, Rc<Vec<Rule>>>>,
+}
+
+impl TreeAttributes<'_> {
+    // Each directory from the top of the tree down to the path's own can have a
+    // .gitattributes, and the deeper ones take precedence - as do later lines within
+    // one file - so the last rule to match wins
+    //
+    fn get(&self, path: &str, name: &str) -> Option<AttrValue> {
+        let mut value = None;
+        let mut dir = String::new();
+
+        for component in std::iter::once("").chain(path.split('/')) {
+            if !component.is_empty() {
+                if !dir.is_empty() {
+                    dir.push('/');
+                }
+                dir.push_str(component);
+            }
+            if dir.len() >= path.len() {
+                break;
+            }
+            let relative = match dir.as_str() {
+                "" => path,
+                dir => &path[dir.len() + 1..],
+            };
+            for rule in self.rules(&dir).iter() {
+                if !rule.pattern.is_match(relative) {
+                    continue;
+                }
+                if let Some((_, rule_value)) = rule.attributes.iter().rev().find(|(n, _)| n == name)
+                {
+                    value = rule_value.clone();
+                }
+            }
+        }
+        value
+    }
+
+    fn rules(&self, dir: &str) -> Rc<Vec<Rule>> {
+        if let Some(rules) = self.files.borrow().get(dir) {
+            return rules.clone();
+        }
+        let file = match dir {
+            "" => ".gitattributes".to_string(),
+            dir => format!("{}/.gitattributes", dir),
+        };
+        let rules = Rc::new(
+            self.tree
+                .get_path(Path::new(&file))
+                .and_then(|entry| self.repo.find_blob(entry.id()))
+                .map(|blob| parse(&String::from_utf8_lossy(blob.content())))
+                .unwrap_or_default(),
+        );
+        self.files
+            .borrow_mut()
+            .insert(dir.to_string(), rules.clone());
+        rules
+    }
+}
+
+// Lines we can't make sense of are skipped rather than failing the whole file, the
+// same as git does. Macro definitions ([attr]...) are left out, apart from the
+// built in "binary", which is "-diff -merge -text"
+//
+fn parse(contents: &str) -> Vec<Rule> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pattern = fields.next()?;
+            if pattern.starts_with('#') || pattern.starts_with("[attr]") {
+                return None;
+            }
+            let attributes = fields
+                .flat_map(|field| match field {
+                    "binary" => vec![
+                        ("binary".to_string(), Some(AttrValue::Set)),
+                        ("diff".to_string(), Some(AttrValue::Unset)),
+                        ("merge".to_string(), Some(AttrValue::Unset)),
+                        ("text".to_string(), Some(AttrValue::Unset)),
+                    ],
+                    field => vec![match (field.strip_prefix('-'), field.strip_prefix('!')) {
+                        (Some(name), _) => (name.to_string(), Some(AttrValue::Unset)),
+                        (_, Some(name)) => (name.to_string(), None),
+                        _ => match field.split_once('=') {
+                            Some((name, value)) => {
+                                (name.to_string(), Some(AttrValue::Value(value.to_string())))
+                            }
+                            None => (field.to_string(), Some(AttrValue::Set)),
+                        },
+                    }],
+                })
+                .collect();
+
+            Some(Rule {
+                pattern: pattern_regex(pattern)?,
+                attributes,
+            })
+        })
+        .collect()
+}
+
+// A .gitattributes pattern as a regex over the path relative to the file's
+// directory. As with .gitignore, a pattern without a slash matches the file name at
+// any depth and one with a slash is anchored to the directory. A trailing slash
+// only matches directories, which never have attributes, so those patterns are
+// dropped
+//
+fn pattern_regex(pattern: &str) -> Option<Regex> {
+    if pattern.ends_with('/') {
+        return None;
+    }
+    let anchored = pattern.contains('/');
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+    let mut regex = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if matches!(chars.peek(), Some('!') | Some('^')) {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            '\\' => regex.push_str(&regex::escape(&chars.next()?.to_string())),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        pattern_regex(pattern).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn patterns_match_the_way_gitignore_ones_do() {
+        assert!(matches("*.dat", "a.dat"));
+        assert!(matches("*.dat", "deep/down/a.dat"));
+        assert!(!matches("*.dat", "a.dat.txt"));
+        assert!(matches("/a.dat", "a.dat"));
+        assert!(!matches("/a.dat", "sub/a.dat"));
+        assert!(matches("sub/*.dat", "sub/a.dat"));
+        assert!(!matches("sub/*.dat", "sub/deeper/a.dat"));
+        assert!(matches("sub/**/*.dat", "sub/deeper/a.dat"));
+        assert!(matches("**/sub/a.dat", "x/sub/a.dat"));
+        assert!(matches("file[0-9].txt", "file1.txt"));
+        assert!(!matches("file[!0-9].txt", "file1.txt"));
+        assert!(matches("a?c", "abc"));
+        assert!(pattern_regex("dir/").is_none());
+    }
+
+    #[test]
+    fn later_lines_win_and_binary_unsets_diff() {
+        let rules = parse("# comment\n*.dat diff\n*.dat -diff filter=lfs\n*.bin binary\n");
+        let value = |path: &str, name: &str| {
+            rules
+                .iter()
+                .filter(|rule| rule.pattern.is_match(path))
+                .filter_map(|rule| rule.attributes.iter().rev().find(|(n, _)| n == name))
+                .next_back()
+                .and_then(|(_, value)| value.clone())
+        };
+        assert_eq!(value("a.dat", "diff"), Some(AttrValue::Unset));
+        assert_eq!(
+            value("a.dat", "filter"),
+            Some(AttrValue::Value("lfs".into()))
+        );
+        assert_eq!(value("a.bin", "diff"), Some(AttrValue::Unset));
+        assert_eq!(value("a.txt", "diff"), None);
+    }
+}
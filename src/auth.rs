@@ -0,0 +1,489 @@
+use crate::{
+    known_hosts::{HostKeyStatus, KnownHosts},
+    settings::Settings,
+};
+use git2::{cert::Cert, CertificateCheckStatus, Cred, CredentialType};
+use std::{
+    cell::Cell,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+// The SSH keys offered to the server, in order. libgit2 calls the credentials
+// callback again each time one is rejected, so the callback counts its attempts
+// and moves on to the next
+//
+#[derive(Debug, PartialEq)]
+enum SshKeySource<'a> {
+    // Every identity the agent (SSH_AUTH_SOCK) holds - libgit2 works through them
+    Agent,
+    // --ssh-key
+    File(&'a str),
+}
+
+// There's no agent to ask on unix without SSH_AUTH_SOCK (Windows uses a named
+// pipe, so there's no equivalent check)
+//
+fn ssh_agent_available() -> bool {
+    !cfg!(unix) || std::env::var_os("SSH_AUTH_SOCK").is_some()
+}
+
+fn ssh_key_sources(settings: &Settings, agent_available: bool) -> Vec<SshKeySource<'_>> {
+    let mut sources = Vec::new();
+    if agent_available {
+        sources.push(SshKeySource::Agent);
+    }
+    if let Some(path) = settings.ssh_key.as_deref() {
+        sources.push(SshKeySource::File(path));
+    }
+    sources
+}
+
+// SSH URLs without a username (host:path) are nearly always for the git user
+//
+const DEFAULT_SSH_USERNAME: &str = "git";
+
+// What to log in as with a --token-file and no -U. GitHub takes any username
+// alongside a token and GitLab any non-empty one, so this suits both
+//
+const DEFAULT_TOKEN_USERNAME: &str = "x-access-token";
+
+// Ask git's configured credential helper(s) for a username / password, the same
+// as git itself would. Prompting is turned off so it can't hang waiting on a
+// terminal when no helper has anything stored
+//
+fn credential_fill(url: &str, username: Option<&str>) -> Result<(String, String), git2::Error> {
+    let helper_error = |e: &dyn std::fmt::Display| {
+        git2::Error::from_str(&format!("Credential helper failed! {}", e))
+    };
+
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| helper_error(&e))?;
+
+    let mut request = format!("url={}\n", url);
+    if let Some(username) = username {
+        request.push_str(&format!("username={}\n", username));
+    }
+    request.push('\n');
+
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| helper_error(&"no stdin"))?
+        .write_all(request.as_bytes())
+        .map_err(|e| helper_error(&e))?;
+
+    let output = child.wait_with_output().map_err(|e| helper_error(&e))?;
+    if !output.status.success() {
+        return Err(helper_error(&format!(
+            "no credentials available for {}",
+            url
+        )));
+    }
+
+    let response = String::from_utf8_lossy(&output.stdout);
+    let value = |key: &str| {
+        response
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(str::to_string)
+    };
+
+    match (value("username"), value("password")) {
+        (Some(username), Some(password)) => Ok((username, password)),
+        _ => Err(helper_error(&format!(
+            "no username / password returned for {}",
+            url
+        ))),
+    }
+}
+
+// Run $GIT_ASKPASS with the prompt as its argument and take the first line it
+// prints, as git does
+//
+fn askpass(prompt: &str) -> Result<String, git2::Error> {
+    let program = std::env::var("GIT_ASKPASS").unwrap_or_default();
+    let output = Command::new(&program)
+        .arg(prompt)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| git2::Error::from_str(&format!("GIT_ASKPASS {} failed! {}", program, e)))?;
+
+    if !output.status.success() {
+        return Err(git2::Error::from_str(&format!(
+            "GIT_ASKPASS {} failed! {}",
+            program, output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string())
+}
+
+fn prompt_username(url: &str) -> Result<String, git2::Error> {
+    eprint!("Username for '{}': ", url);
+    let mut username = String::new();
+    io::stdin()
+        .read_line(&mut username)
+        .map_err(|e| git2::Error::from_str(&format!("Username prompt failed! {}", e)))?;
+    Ok(username.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// libgit2 keeps calling the credentials callback for as long as it's handed
+// credentials, even when the server rejects every one - these count the calls
+// for a single clone (or submodule update)
+//
+#[derive(Default)]
+pub(crate) struct AuthAttempts {
+    total: Cell<u32>,
+    ssh: Cell<usize>,
+}
+
+pub(crate) fn credentials(
+    settings: &Settings,
+    attempts: &AuthAttempts,
+    url: &str,
+    username_from_url: Option<&str>,
+    allowed_types: CredentialType,
+) -> Result<Cred, git2::Error> {
+    // The git protocol has no authentication at all, so there's nothing sensible to
+    // hand over if it's ever asked for
+    //
+    if url.starts_with("git://") {
+        return Err(git2::Error::from_str(&format!(
+            "{} asked for credentials, but git:// is anonymous-only - use an https:// or ssh:// URL for private repos",
+            url
+        )));
+    }
+
+    let attempt = attempts.total.get() + 1;
+    attempts.total.set(attempt);
+    if attempt > settings.max_auth_attempts {
+        return Err(git2::Error::from_str(&format!(
+            "Authentication for {} failed - giving up after --max-auth-attempts {}",
+            url, settings.max_auth_attempts
+        )));
+    }
+
+    let ssh_username = username_from_url.unwrap_or(DEFAULT_SSH_USERNAME);
+
+    if allowed_types.contains(CredentialType::USERNAME) {
+        // Asked for just the username first, when the URL doesn't have one
+        //
+        Cred::username(ssh_username)
+    } else if allowed_types.is_ssh_key() {
+        let attempt = attempts.ssh.get();
+        attempts.ssh.set(attempt + 1);
+
+        match ssh_key_sources(settings, ssh_agent_available()).get(attempt) {
+            Some(SshKeySource::Agent) => Cred::ssh_key_from_agent(ssh_username),
+            Some(SshKeySource::File(path)) => {
+                Cred::ssh_key(ssh_username, None, std::path::Path::new(path), None)
+            }
+            None => Err(git2::Error::from_str(&format!(
+                "SSH authentication as {} failed - {}",
+                ssh_username,
+                match (ssh_agent_available(), settings.ssh_key.is_some()) {
+                    (true, true) => "the agent's keys and --ssh-key were all rejected",
+                    (true, false) => "the agent's keys were all rejected (or it has none loaded - check ssh-add -l)",
+                    (false, true) => "--ssh-key was rejected (SSH_AUTH_SOCK isn't set, so no agent was tried)",
+                    (false, false) => "SSH_AUTH_SOCK isn't set, so there's no agent to get keys from - start one or pass --ssh-key",
+                }
+            ))),
+        }
+    } else if allowed_types.is_user_pass_plaintext() {
+        // Provide plaintext username / password if provided in settings
+        //
+        match (
+            settings.plaintext_username.as_ref(),
+            settings.plaintext_password.as_ref(),
+        ) {
+            (Some(username), Some(password)) => Cred::userpass_plaintext(username, password),
+            (None, Some(password)) if settings.token_auth => Cred::userpass_plaintext(
+                username_from_url.unwrap_or(DEFAULT_TOKEN_USERNAME),
+                password,
+            ),
+            (username, _) if settings.use_credential_helper => {
+                let (username, password) =
+                    credential_fill(url, username.map(String::as_str).or(username_from_url))?;
+                Cred::userpass_plaintext(&username, &password)
+            }
+            (username, password) if std::env::var_os("GIT_ASKPASS").is_some() => {
+                let username = match username {
+                    Some(username) => username.clone(),
+                    None => askpass(&format!("Username for '{}': ", url))?,
+                };
+                let password = match password {
+                    Some(password) => password.clone(),
+                    None => askpass(&format!("Password for '{}': ", url))?,
+                };
+                Cred::userpass_plaintext(&username, &password)
+            }
+            (username, password) if settings.interactive => {
+                let username = match username {
+                    Some(username) => username.clone(),
+                    None => prompt_username(url)?,
+                };
+                let password = match password {
+                    Some(password) => password.clone(),
+                    None => rpassword::prompt_password(format!("Password for '{}': ", url))
+                        .map_err(|e| {
+                            git2::Error::from_str(&format!("Password prompt failed! {}", e))
+                        })?,
+                };
+                Cred::userpass_plaintext(&username, &password)
+            }
+            _ => Err(git2::Error::from_str(&format!(
+                "{} asked for a username and password, but none were given - pass -U and -P, use --use-credential-helper or GIT_ASKPASS, or --interactive to be prompted",
+                url
+            ))),
+        }
+    } else {
+        Err(git2::Error::from_str(&format!(
+            "The server asked for {} credentials, which aren't supported - only SSH keys (agent or --ssh-key) and HTTPS username / password (-U / -P, --use-credential-helper, GIT_ASKPASS or --interactive) can be supplied",
+            credential_type_names(allowed_types)
+        )))
+    }
+}
+
+fn credential_type_names(allowed_types: CredentialType) -> String {
+    [
+        (CredentialType::USER_PASS_PLAINTEXT, "USER_PASS_PLAINTEXT"),
+        (CredentialType::SSH_KEY, "SSH_KEY"),
+        (CredentialType::SSH_CUSTOM, "SSH_CUSTOM"),
+        (CredentialType::DEFAULT, "DEFAULT"),
+        (CredentialType::SSH_INTERACTIVE, "SSH_INTERACTIVE"),
+        (CredentialType::USERNAME, "USERNAME"),
+        (CredentialType::SSH_MEMORY, "SSH_MEMORY"),
+    ]
+    .iter()
+    .filter(|(credential_type, _name)| allowed_types.contains(*credential_type))
+    .map(|(_credential_type, name)| *name)
+    .collect::<Vec<&str>>()
+    .join(" / ")
+}
+
+// The port of an ssh:// URL, when it's given. scp-style URLs (user@host:path)
+// are always port 22
+//
+fn ssh_port(url: &str) -> Option<u16> {
+    let authority = url.strip_prefix("ssh://")?.split('/').next()?;
+    let host_port = authority.rsplit('@').next()?;
+    let (_host, port) = host_port.rsplit_once(':')?;
+    port.parse().ok()
+}
+
+// Check an SSH server's host key against known_hosts. Anything else (TLS
+// certificates) is left to libgit2's own checks
+//
+pub(crate) fn certificate_check(
+    settings: &Settings,
+    url: &str,
+    cert: &Cert,
+    host: &str,
+) -> Result<CertificateCheckStatus, git2::Error> {
+    let hostkey = match cert.as_hostkey() {
+        Some(hostkey) => hostkey,
+        None => return Ok(CertificateCheckStatus::CertificatePassthrough),
+    };
+    if settings.insecure_skip_host_key_check {
+        return Ok(CertificateCheckStatus::CertificateOk);
+    }
+
+    let key = hostkey
+        .hostkey()
+        .ok_or_else(|| git2::Error::from_str("Server did not provide its SSH host key"))?;
+    let key_type = hostkey
+        .hostkey_type()
+        .map(|key_type| key_type.name())
+        .unwrap_or("unknown");
+
+    // known_hosts lists anything not on port 22 as [host]:port
+    //
+    let known_host = match ssh_port(url) {
+        Some(port) if port != 22 => format!("[{}]:{}", host, port),
+        _ => host.to_string(),
+    };
+
+    let known_hosts_path = match settings.known_hosts.as_ref() {
+        Some(path) => path.clone(),
+        None => format!(
+            "{}/.ssh/known_hosts",
+            std::env::var("HOME").unwrap_or_default()
+        ),
+    };
+    let known_hosts =
+        KnownHosts::load(&known_hosts_path).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+
+    match known_hosts.check(&known_host, key) {
+        HostKeyStatus::Known => Ok(CertificateCheckStatus::CertificateOk),
+        HostKeyStatus::Mismatch => Err(git2::Error::from_str(&format!(
+            "The {} host key for {} does not match {} - someone may be intercepting the connection",
+            key_type, known_host, known_hosts_path
+        ))),
+        HostKeyStatus::Revoked => Err(git2::Error::from_str(&format!(
+            "The {} host key for {} is marked @revoked in {}",
+            key_type, known_host, known_hosts_path
+        ))),
+        HostKeyStatus::Unknown => Err(git2::Error::from_str(&format!(
+            "{} is not in {} - add it (e.g. ssh-keyscan {} >> {}) or pass --insecure-skip-host-key-check",
+            known_host, known_hosts_path, host, known_hosts_path
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "ssh://example.com/org/repo.git";
+
+    fn with_ssh_key() -> Settings {
+        Settings {
+            ssh_key: Some("/keys/id_ed25519".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ssh_keys_are_offered_agent_first() {
+        let settings = with_ssh_key();
+
+        assert_eq!(
+            ssh_key_sources(&settings, true),
+            [SshKeySource::Agent, SshKeySource::File("/keys/id_ed25519")]
+        );
+        assert_eq!(
+            ssh_key_sources(&settings, false),
+            [SshKeySource::File("/keys/id_ed25519")]
+        );
+        assert!(ssh_key_sources(&Settings::default(), false).is_empty());
+    }
+
+    #[test]
+    fn ssh_moves_through_the_keys_then_gives_up() {
+        let settings = with_ssh_key();
+        let attempts = AuthAttempts::default();
+
+        // Without a username in the URL, git is asked for first
+        //
+        let username =
+            credentials(&settings, &attempts, URL, None, CredentialType::USERNAME).unwrap();
+        assert_eq!(username.credtype(), CredentialType::USERNAME.bits());
+
+        // Then each key in turn, each one the server rejects calling back again
+        //
+        for _source in ssh_key_sources(&settings, ssh_agent_available()) {
+            let key =
+                credentials(&settings, &attempts, URL, None, CredentialType::SSH_KEY).unwrap();
+            assert_eq!(key.credtype(), CredentialType::SSH_KEY.bits());
+        }
+
+        let error = credentials(&settings, &attempts, URL, None, CredentialType::SSH_KEY)
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .message()
+                .starts_with("SSH authentication as git failed"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn unsupported_credential_types_are_named_in_the_error() {
+        let error = credentials(
+            &Settings::default(),
+            &AuthAttempts::default(),
+            URL,
+            Some("git"),
+            CredentialType::SSH_INTERACTIVE | CredentialType::SSH_CUSTOM,
+        )
+        .err()
+        .unwrap();
+
+        assert!(
+            error
+                .message()
+                .starts_with("The server asked for SSH_CUSTOM / SSH_INTERACTIVE credentials, which aren't supported"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn authentication_gives_up_after_max_auth_attempts() {
+        let settings = Settings {
+            plaintext_username: Some("user".to_string()),
+            plaintext_password: Some("wrong".to_string()),
+            max_auth_attempts: 3,
+            ..Default::default()
+        };
+        let attempts = AuthAttempts::default();
+        let url = "https://example.com/org/repo.git";
+
+        for _attempt in 0..3 {
+            credentials(
+                &settings,
+                &attempts,
+                url,
+                None,
+                CredentialType::USER_PASS_PLAINTEXT,
+            )
+            .unwrap();
+        }
+        let error = credentials(
+            &settings,
+            &attempts,
+            url,
+            None,
+            CredentialType::USER_PASS_PLAINTEXT,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.message(),
+            "Authentication for https://example.com/org/repo.git failed - giving up after --max-auth-attempts 3"
+        );
+
+        // Each clone counts its own attempts
+        //
+        assert!(credentials(
+            &settings,
+            &AuthAttempts::default(),
+            url,
+            None,
+            CredentialType::USER_PASS_PLAINTEXT
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn git_protocol_never_hands_over_credentials() {
+        let error = credentials(
+            &with_ssh_key(),
+            &AuthAttempts::default(),
+            "git://example.com/repo.git",
+            None,
+            CredentialType::USER_PASS_PLAINTEXT | CredentialType::SSH_KEY,
+        )
+        .err()
+        .unwrap();
+
+        assert!(
+            error.message().contains("git:// is anonymous-only"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,458 @@
+use crate::{commit::Commit, error::Error, sink::OutputSink};
+use serde_json::{json, Value};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::Write,
+};
+
+// Rows are encoded into a block until it reaches about this size, then the block is
+// written out whole - a reader can only split the file up at block boundaries
+//
+const BLOCK_BYTES: usize = 64 * 1024;
+
+// The subset of Avro types the rows need
+//
+pub enum AvroType {
+    Boolean,
+    Int,
+    Long,
+    Str,
+    // A union with null, null first so the field can default to it
+    Optional(&'static AvroType),
+    Array(&'static AvroType),
+    Record(&'static str, &'static [(&'static str, AvroType)]),
+}
+
+use AvroType::*;
+
+const HUNK: AvroType = Record(
+    "Hunk",
+    &[
+        ("old_start", Long),
+        ("old_lines", Long),
+        ("new_start", Long),
+        ("new_lines", Long),
+    ],
+);
+
+// Mirrors FlatCommit, field for field and in the same order. Anything held in a u32
+// is a long, as Avro's int is signed; enums are written as their names
+//
+pub const FLAT_COMMIT: AvroType = Record(
+    "FlatCommit",
+    &[
+        ("schema_version", Int),
+        ("record_kind", Optional(&Str)),
+        ("id", Str),
+        ("short_id", Str),
+        ("tree_id", Optional(&Str)),
+        ("repo_url", Str),
+        ("repo_root", Optional(&Str)),
+        ("seq", Optional(&Long)),
+        ("timestamp", Long),
+        ("author_tz_offset_minutes", Int),
+        ("committer_tz_offset_minutes", Int),
+        ("author_name", Optional(&Str)),
+        ("author_email", Optional(&Str)),
+        ("message", Str),
+        ("subject", Optional(&Str)),
+        ("body", Optional(&Str)),
+        ("message_length", Optional(&Long)),
+        ("encoding", Optional(&Str)),
+        ("type", Str),
+        ("is_merge", Boolean),
+        ("squash", Optional(&Boolean)),
+        ("pr_number", Optional(&Long)),
+        ("parent_count", Long),
+        ("is_root", Boolean),
+        ("shallow", Boolean),
+        ("is_working", Boolean),
+        ("branches", Optional(&Array(&Str))),
+        ("record_hash", Optional(&Str)),
+        ("path", Str),
+        ("old_path", Optional(&Str)),
+        ("status", Str),
+        ("similarity", Optional(&Int)),
+        ("language", Optional(&Str)),
+        ("lines_added", Long),
+        ("lines_removed", Long),
+        ("lines_modified", Long),
+        ("hunks_added", Long),
+        ("hunks_removed", Long),
+        ("hunks_modified", Long),
+        ("additions_in_modified_hunks", Optional(&Long)),
+        ("deletions_in_modified_hunks", Optional(&Long)),
+        ("words_added", Optional(&Long)),
+        ("words_removed", Optional(&Long)),
+        ("binary", Boolean),
+        ("whitespace_only", Boolean),
+        ("submodule_old_id", Optional(&Str)),
+        ("submodule_new_id", Optional(&Str)),
+        ("lfs", Boolean),
+        ("lfs_size", Optional(&Long)),
+        ("old_size", Optional(&Long)),
+        ("new_size", Optional(&Long)),
+        ("hunks", Optional(&Array(&HUNK))),
+    ],
+);
+
+impl AvroType {
+    pub fn schema(&self) -> Value {
+        match self {
+            Boolean => json!("boolean"),
+            Int => json!("int"),
+            Long => json!("long"),
+            Str => json!("string"),
+            Optional(inner) => json!(["null", inner.schema()]),
+            Array(items) => json!({ "type": "array", "items": items.schema() }),
+            Record(name, fields) => json!({
+                "type": "record",
+                "name": name,
+                "fields": fields
+                    .iter()
+                    .map(|(name, field_type)| match field_type {
+                        Optional(_) => json!({ "name": name, "type": field_type.schema(), "default": null }),
+                        _ => json!({ "name": name, "type": field_type.schema() }),
+                    })
+                    .collect::<Vec<_>>(),
+            }),
+        }
+    }
+
+    // Encodes a row as serialized by serde, so the value has to have the shape the
+    // schema says it does - a mismatch means this file has fallen behind FlatCommit
+    //
+    fn encode(&self, value: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+        match (self, value) {
+            (Boolean, Value::Bool(b)) => out.push(u8::from(*b)),
+            (Int | Long, Value::Number(n)) => match n.as_i64() {
+                Some(n) => write_long(out, n),
+                None => return Err(format!("{} is out of range", n)),
+            },
+            (Str, Value::String(s)) => write_bytes(out, s.as_bytes()),
+            (Optional(_), Value::Null) => write_long(out, 0),
+            (Optional(inner), value) => {
+                write_long(out, 1);
+                inner.encode(value, out)?;
+            }
+            (Array(items), Value::Array(values)) => {
+                if !values.is_empty() {
+                    write_long(out, values.len() as i64);
+                    for value in values {
+                        items.encode(value, out)?;
+                    }
+                }
+                write_long(out, 0);
+            }
+            (Record(_, fields), Value::Object(map)) => {
+                for (name, field_type) in fields.iter() {
+                    field_type
+                        .encode(map.get(*name).unwrap_or(&Value::Null), out)
+                        .map_err(|e| format!("{}: {}", name, e))?;
+                }
+            }
+            (_, value) => return Err(format!("unexpected {}", value)),
+        }
+        Ok(())
+    }
+}
+
+// Writes an Avro object container file - a header carrying the schema, then blocks
+// of rows each followed by the file's sync marker. Blocks aren't compressed
+//
+pub struct AvroSink<W: Write> {
+    out: W,
+    sync: [u8; 16],
+    block: Vec<u8>,
+    rows: i64,
+}
+
+impl<W: Write> AvroSink<W> {
+    pub fn new(mut out: W) -> Result<AvroSink<W>, Error> {
+        let sync = sync_marker();
+
+        let mut header = b"Obj\x01".to_vec();
+        write_long(&mut header, 2);
+        write_bytes(&mut header, b"avro.schema");
+        write_bytes(&mut header, FLAT_COMMIT.schema().to_string().as_bytes());
+        write_bytes(&mut header, b"avro.codec");
+        write_bytes(&mut header, b"null");
+        write_long(&mut header, 0);
+        header.extend_from_slice(&sync);
+
+        out.write_all(&header)
+            .map_err(|e| Error::io("Write failed", e))?;
+
+        Ok(AvroSink {
+            out,
+            sync,
+            block: Vec::new(),
+            rows: 0,
+        })
+    }
+
+    fn write_block(&mut self) -> Result<(), Error> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+
+        let mut counts = Vec::new();
+        write_long(&mut counts, self.rows);
+        write_long(&mut counts, self.block.len() as i64);
+
+        self.out
+            .write_all(&counts)
+            .and_then(|_| self.out.write_all(&self.block))
+            .and_then(|_| self.out.write_all(&self.sync))
+            .map_err(|e| Error::io("Write failed", e))?;
+        self.block.clear();
+        self.rows = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> OutputSink for AvroSink<W> {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        for flat in commit.flat() {
+            let value = serde_json::to_value(&flat)
+                .map_err(|e| Error::serde(format!("Serde failed for commit {}", commit.id), e))?;
+            FLAT_COMMIT
+                .encode(&value, &mut self.block)
+                .map_err(|e| Error::Serde {
+                    context: format!("Avro failed for commit {}", commit.id),
+                    source: e.into(),
+                })?;
+            self.rows += 1;
+        }
+
+        if self.block.len() >= BLOCK_BYTES {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        self.write_block()?;
+        self.out.flush().map_err(|e| Error::io("Write failed", e))
+    }
+}
+
+// Longs (and ints, and every length) are zig-zag encoded varints
+//
+fn write_long(out: &mut Vec<u8>, n: i64) {
+    let mut n = ((n << 1) ^ (n >> 63)) as u64;
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_long(out, bytes.len() as i64);
+    out.extend_from_slice(bytes);
+}
+
+// The marker only has to be unlikely to turn up inside a block. RandomState is
+// seeded randomly per process, which is plenty for that
+//
+fn sync_marker() -> [u8; 16] {
+    let mut marker = [0; 16];
+    for (i, chunk) in marker.chunks_mut(8).enumerate() {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(i);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    marker
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commit::tests::commit_changing, diff::Hunk};
+    use serde::de::{Deserialize, Deserializer, IgnoredAny, MapAccess, Visitor};
+
+    // Reads Avro back as JSON going only by the schema in the file's header, so it
+    // checks the header as much as the rows
+    //
+    struct Reader<'a> {
+        bytes: &'a [u8],
+    }
+
+    impl Reader<'_> {
+        fn take(&mut self, n: usize) -> &[u8] {
+            let (taken, rest) = self.bytes.split_at(n);
+            self.bytes = rest;
+            taken
+        }
+
+        fn long(&mut self) -> i64 {
+            let (mut n, mut shift) = (0u64, 0);
+            loop {
+                let byte = self.take(1)[0];
+                n |= u64::from(byte & 0x7f) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            (n >> 1) as i64 ^ -((n & 1) as i64)
+        }
+
+        fn string(&mut self) -> String {
+            let length = self.long() as usize;
+            String::from_utf8(self.take(length).to_vec()).unwrap()
+        }
+
+        fn value(&mut self, schema: &Value) -> Value {
+            match schema {
+                Value::String(name) => match name.as_str() {
+                    "null" => Value::Null,
+                    "boolean" => Value::Bool(self.take(1)[0] == 1),
+                    "int" | "long" => json!(self.long()),
+                    "string" => json!(self.string()),
+                    other => panic!("unexpected type {}", other),
+                },
+                Value::Array(branches) => {
+                    let branch = self.long() as usize;
+                    self.value(&branches[branch])
+                }
+                Value::Object(schema) => match schema["type"].as_str().unwrap() {
+                    "array" => {
+                        let mut items = Vec::new();
+                        loop {
+                            let count = self.long();
+                            if count == 0 {
+                                break;
+                            }
+                            for _ in 0..count {
+                                items.push(self.value(&schema["items"]));
+                            }
+                        }
+                        Value::Array(items)
+                    }
+                    "record" => Value::Object(
+                        schema["fields"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|field| {
+                                let name = field["name"].as_str().unwrap().to_string();
+                                (name, self.value(&field["type"]))
+                            })
+                            .collect(),
+                    ),
+                    other => panic!("unexpected type {}", other),
+                },
+                other => panic!("unexpected schema {}", other),
+            }
+        }
+    }
+
+    // The header's schema and every row in the file
+    //
+    fn read_file(bytes: &[u8]) -> (Value, Vec<Value>) {
+        let mut reader = Reader { bytes };
+        assert_eq!(reader.take(4), b"Obj\x01");
+
+        let mut metadata = serde_json::Map::new();
+        loop {
+            let count = reader.long();
+            if count == 0 {
+                break;
+            }
+            for _ in 0..count {
+                let key = reader.string();
+                metadata.insert(key, json!(reader.string()));
+            }
+        }
+        assert_eq!(metadata["avro.codec"], "null");
+        let schema: Value =
+            serde_json::from_str(metadata["avro.schema"].as_str().unwrap()).unwrap();
+        let sync = reader.take(16).to_vec();
+
+        let mut rows = Vec::new();
+        while !reader.bytes.is_empty() {
+            let count = reader.long();
+            let size = reader.long() as usize;
+            let block_start = reader.bytes.len();
+            for _ in 0..count {
+                rows.push(reader.value(&schema));
+            }
+            assert_eq!(block_start - reader.bytes.len(), size);
+            assert_eq!(reader.take(16), sync);
+        }
+        (schema, rows)
+    }
+
+    // A JSON object's keys in the order they were written
+    //
+    struct Keys(Vec<String>);
+
+    impl<'de> Deserialize<'de> for Keys {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Keys, D::Error> {
+            struct KeysVisitor;
+
+            impl<'de> Visitor<'de> for KeysVisitor {
+                type Value = Keys;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("an object")
+                }
+
+                fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Keys, A::Error> {
+                    let mut keys = Vec::new();
+                    while let Some((key, IgnoredAny)) = map.next_entry::<String, IgnoredAny>()? {
+                        keys.push(key);
+                    }
+                    Ok(Keys(keys))
+                }
+            }
+
+            deserializer.deserialize_map(KeysVisitor)
+        }
+    }
+
+    #[test]
+    fn rows_read_back_as_they_were_serialized() {
+        let mut first = commit_changing(&["a.txt", "b.txt"]);
+        first.branches = Some(vec!["main".to_string(), "feature".to_string()]);
+        first.author_name = Some("Ada".to_string());
+        first.seq = Some(3);
+        first.timestamp = -1;
+        first.changes[0].similarity = Some(87);
+        first.changes[0].old_path = Some("old.txt".to_string());
+        first.changes[1].hunks = Some(vec![Hunk {
+            old_start: 1,
+            old_lines: 0,
+            new_start: 1,
+            new_lines: 2,
+        }]);
+        let mut second = commit_changing(&["c.txt"]);
+        second.branches = None;
+        second.message = "ü\nnon-ASCII".to_string();
+
+        let mut out = Vec::new();
+        let mut sink: Box<dyn OutputSink> = Box::new(AvroSink::new(&mut out).unwrap());
+        sink.write_commit(&first).unwrap();
+        sink.write_commit(&second).unwrap();
+        sink.finish().unwrap();
+
+        let (schema, rows) = read_file(&out);
+        let expected: Vec<_> = first.flat().chain(second.flat()).collect();
+        assert_eq!(rows.len(), expected.len());
+
+        let schema_names: Vec<&str> = schema["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["name"].as_str().unwrap())
+            .collect();
+        for (row, flat) in rows.iter().zip(&expected) {
+            let Keys(keys) = serde_json::from_str(&serde_json::to_string(flat).unwrap()).unwrap();
+            assert_eq!(schema_names, keys);
+            assert_eq!(*row, serde_json::to_value(flat).unwrap());
+        }
+    }
+}
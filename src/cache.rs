@@ -0,0 +1,79 @@
+use crate::{diff::FileChange, error::Error, settings::Settings, SCHEMA_VERSION};
+use git2::Oid;
+use std::{fs, path::PathBuf};
+
+// File changes from earlier runs, stored as JSON under --cache-dir. A diff is
+// identified by the pair of trees it's between - the same pair always gives the
+// same changes, whichever commit (or repo) it came from. The options that change
+// what gets extracted are part of the key too
+//
+pub struct DiffCache {
+    dir: PathBuf,
+    options: String,
+}
+
+impl DiffCache {
+    pub fn new(dir: &str, settings: &Settings) -> Result<DiffCache, Error> {
+        fs::create_dir_all(dir)
+            .map_err(|e| Error::io(format!("Unable to create cache dir {}", dir), e))?;
+
+        let options = format!(
+            "v{}-r{}-c{}-a{}-s{}-m{}-w{}-h{}-e{}-t{}",
+            SCHEMA_VERSION,
+            settings.find_renames as u8,
+            settings.find_copies as u8,
+            settings.attribute_renames as u8,
+            settings.submodule_changes as u8,
+            settings.with_sizes as u8,
+            settings.word_diff as u8,
+            settings.with_hunks as u8,
+            settings.with_modified_hunk_lines as u8,
+            settings.rename_threshold,
+        );
+
+        Ok(DiffCache {
+            dir: PathBuf::from(dir),
+            options,
+        })
+    }
+
+    // Sharded on the start of the tree id, the same as git's own object store
+    //
+    fn path(&self, parent_tree: Option<Oid>, tree: Oid) -> PathBuf {
+        let tree = tree.to_string();
+        let parent_tree = parent_tree
+            .map(|oid| oid.to_string())
+            .unwrap_or_else(|| "root".to_string());
+
+        self.dir
+            .join(&tree[..2])
+            .join(format!("{}-{}-{}.json", parent_tree, tree, self.options))
+    }
+
+    // Anything unreadable is treated as a miss and the diff worked out again
+    //
+    pub fn get(&self, parent_tree: Option<Oid>, tree: Oid) -> Option<Vec<FileChange>> {
+        let contents = fs::read(self.path(parent_tree, tree)).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    pub fn put(
+        &self,
+        parent_tree: Option<Oid>,
+        tree: Oid,
+        changes: &[FileChange],
+    ) -> Result<(), Error> {
+        let path = self.path(parent_tree, tree);
+
+        // Written to the side and renamed into place, so a reader never sees half
+        // a file
+        //
+        let partial = path.with_extension("partial");
+        let json = serde_json::to_vec(changes).map_err(|e| Error::serde("Cache failed", e))?;
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&partial, json))
+            .and_then(|_| fs::rename(&partial, &path))
+            .map_err(|e| Error::io("Cache failed", e))
+    }
+}
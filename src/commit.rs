@@ -0,0 +1,416 @@
+use crate::diff::{ChangeStatus, FileChange, Hunk};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub enum CommitType {
+    #[default]
+    Normal,
+    Merge,
+}
+
+// A commit and its file changes, as it's written with --nested
+//
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct Commit {
+    pub schema_version: u32,
+    pub record_kind: Option<&'static str>,
+    pub id: String,
+    pub short_id: String,
+    pub tree_id: Option<String>,
+    pub repo_url: String,
+    pub repo_root: Option<String>,
+    pub seq: Option<u64>,
+    pub timestamp: i64,
+    pub author_tz_offset_minutes: i32,
+    pub committer_tz_offset_minutes: i32,
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+    pub message: String,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+    pub message_length: Option<usize>,
+    pub encoding: Option<String>,
+    pub r#type: CommitType,
+    pub is_merge: bool,
+    pub squash: Option<bool>,
+    pub pr_number: Option<u64>,
+    pub parent_count: u32,
+    pub is_root: bool,
+    pub shallow: bool,
+    pub is_working: bool,
+    pub branches: Option<Vec<String>>,
+    pub record_hash: Option<String>,
+    pub changes: Vec<FileChange>,
+}
+
+// A single output row - one per file changed in a commit - owning its fields, for
+// keeping rows around or reading them back in. From a FlatCommitRef
+//
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct FlatCommit {
+    pub schema_version: u32,
+    pub record_kind: Option<String>,
+    pub id: String,
+    pub short_id: String,
+    pub tree_id: Option<String>,
+    pub repo_url: String,
+    pub repo_root: Option<String>,
+    pub seq: Option<u64>,
+    pub timestamp: i64,
+    pub author_tz_offset_minutes: i32,
+    pub committer_tz_offset_minutes: i32,
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+    pub message: String,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+    pub message_length: Option<usize>,
+    pub encoding: Option<String>,
+    pub r#type: CommitType,
+    pub is_merge: bool,
+    pub squash: Option<bool>,
+    pub pr_number: Option<u64>,
+    pub parent_count: u32,
+    pub is_root: bool,
+    pub shallow: bool,
+    pub is_working: bool,
+    pub branches: Option<Vec<String>>,
+    pub record_hash: Option<String>,
+    pub path: String,
+    pub old_path: Option<String>,
+    pub status: ChangeStatus,
+    pub similarity: Option<u8>,
+    pub language: Option<String>,
+    pub lines_added: u32,
+    pub lines_removed: u32,
+    pub lines_modified: u32,
+    pub hunks_added: u32,
+    pub hunks_removed: u32,
+    pub hunks_modified: u32,
+    pub additions_in_modified_hunks: Option<u32>,
+    pub deletions_in_modified_hunks: Option<u32>,
+    pub words_added: Option<u32>,
+    pub words_removed: Option<u32>,
+    pub binary: bool,
+    pub whitespace_only: bool,
+    pub submodule_old_id: Option<String>,
+    pub submodule_new_id: Option<String>,
+    pub lfs: bool,
+    pub lfs_size: Option<u64>,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    pub hunks: Option<Vec<Hunk>>,
+}
+
+// The same row, borrowing everything from the owning Commit / FileChange so that
+// flattening doesn't allocate per row (other than a record_hash, when there is
+// one). It's what the sinks write
+//
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct FlatCommitRef<'a> {
+    pub schema_version: u32,
+    pub record_kind: Option<&'static str>,
+    pub id: &'a str,
+    pub short_id: &'a str,
+    pub tree_id: Option<&'a str>,
+    pub repo_url: &'a str,
+    pub repo_root: Option<&'a str>,
+    pub seq: Option<u64>,
+    pub timestamp: i64,
+    pub author_tz_offset_minutes: i32,
+    pub committer_tz_offset_minutes: i32,
+    pub author_name: Option<&'a str>,
+    pub author_email: Option<&'a str>,
+    pub message: &'a str,
+    pub subject: Option<&'a str>,
+    pub body: Option<&'a str>,
+    pub message_length: Option<usize>,
+    pub encoding: Option<&'a str>,
+    pub r#type: &'a CommitType,
+    pub is_merge: bool,
+    pub squash: Option<bool>,
+    pub pr_number: Option<u64>,
+    pub parent_count: u32,
+    pub is_root: bool,
+    pub shallow: bool,
+    pub is_working: bool,
+    pub branches: Option<&'a [String]>,
+    pub record_hash: Option<String>,
+    pub path: &'a str,
+    pub old_path: Option<&'a str>,
+    pub status: ChangeStatus,
+    pub similarity: Option<u8>,
+    pub language: Option<&'a str>,
+    pub lines_added: u32,
+    pub lines_removed: u32,
+    pub lines_modified: u32,
+    pub hunks_added: u32,
+    pub hunks_removed: u32,
+    pub hunks_modified: u32,
+    pub additions_in_modified_hunks: Option<u32>,
+    pub deletions_in_modified_hunks: Option<u32>,
+    pub words_added: Option<u32>,
+    pub words_removed: Option<u32>,
+    pub binary: bool,
+    pub whitespace_only: bool,
+    pub submodule_old_id: Option<&'a str>,
+    pub submodule_new_id: Option<&'a str>,
+    pub lfs: bool,
+    pub lfs_size: Option<u64>,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    pub hunks: Option<&'a [Hunk]>,
+}
+
+// What --emit-errors writes for a repo that failed, or a commit in it that couldn't
+// be diffed (with its id, and kind "diff"), in among the commits. Told apart from
+// them by record_kind, which is "commit" in theirs
+//
+#[derive(Serialize, Debug)]
+pub struct ErrorRecord {
+    pub schema_version: u32,
+    pub record_kind: &'static str,
+    pub repo_url: String,
+    pub id: Option<String>,
+    pub kind: &'static str,
+    pub error: String,
+}
+
+// A SHA-256 (hex) of a record serialized with its keys sorted (serde_json's maps
+// are ordered by key), so neither the order fields are declared in nor the output
+// format affects it. seq is left out, being only the record's position in this
+// particular run
+//
+pub(crate) fn hash_record(record: &impl Serialize) -> Result<String, serde_json::Error> {
+    let mut record = serde_json::to_value(record)?;
+    if let Some(fields) = record.as_object_mut() {
+        fields.remove("seq");
+        fields.remove("record_hash");
+    }
+    Ok(format!("{:x}", Sha256::digest(record.to_string())))
+}
+
+impl Commit {
+    // One row per file changed. Rows are produced lazily and only borrow from the
+    // commit, so a commit touching tens of thousands of files doesn't need them all
+    // in memory at once
+    //
+    pub fn flat(&self) -> impl Iterator<Item = FlatCommitRef<'_>> {
+        self.changes
+            .iter()
+            .map(move |change| FlatCommitRef::new(self, change))
+    }
+}
+
+impl<'a> FlatCommitRef<'a> {
+    pub fn new(commit: &'a Commit, change: &'a FileChange) -> FlatCommitRef<'a> {
+        let mut row = FlatCommitRef {
+            schema_version: commit.schema_version,
+            record_kind: commit.record_kind,
+            id: &commit.id,
+            short_id: &commit.short_id,
+            tree_id: commit.tree_id.as_deref(),
+            repo_url: &commit.repo_url,
+            repo_root: commit.repo_root.as_deref(),
+            seq: commit.seq,
+            timestamp: commit.timestamp,
+            author_tz_offset_minutes: commit.author_tz_offset_minutes,
+            committer_tz_offset_minutes: commit.committer_tz_offset_minutes,
+            author_name: commit.author_name.as_deref(),
+            author_email: commit.author_email.as_deref(),
+            message: &commit.message,
+            subject: commit.subject.as_deref(),
+            body: commit.body.as_deref(),
+            message_length: commit.message_length,
+            encoding: commit.encoding.as_deref(),
+            r#type: &commit.r#type,
+            is_merge: commit.is_merge,
+            squash: commit.squash,
+            pr_number: commit.pr_number,
+            parent_count: commit.parent_count,
+            is_root: commit.is_root,
+            shallow: commit.shallow,
+            is_working: commit.is_working,
+            branches: commit.branches.as_deref(),
+            record_hash: None,
+            path: &change.path,
+            old_path: change.old_path.as_deref(),
+            status: change.status,
+            similarity: change.similarity,
+            language: change.language.as_deref(),
+            lines_added: change.lines_added,
+            lines_removed: change.lines_removed,
+            lines_modified: change.lines_modified,
+            hunks_added: change.hunks_added,
+            hunks_removed: change.hunks_removed,
+            hunks_modified: change.hunks_modified,
+            additions_in_modified_hunks: change.additions_in_modified_hunks,
+            deletions_in_modified_hunks: change.deletions_in_modified_hunks,
+            words_added: change.words_added,
+            words_removed: change.words_removed,
+            binary: change.binary,
+            whitespace_only: change.whitespace_only,
+            submodule_old_id: change.submodule_old_id.as_deref(),
+            submodule_new_id: change.submodule_new_id.as_deref(),
+            lfs: change.lfs,
+            lfs_size: change.lfs_size,
+            old_size: change.old_size,
+            new_size: change.new_size,
+            hunks: change.hunks.as_deref(),
+        };
+
+        // With record hashes on (the commit has one), a row is hashed on its own -
+        // the commit's fields along with this row's change - as that's the record
+        // written. One that can't be serialized is left without, as writing it
+        // fails anyway
+        //
+        if commit.record_hash.is_some() {
+            row.record_hash = hash_record(&row).ok();
+        }
+        row
+    }
+}
+
+impl From<FlatCommitRef<'_>> for FlatCommit {
+    fn from(row: FlatCommitRef<'_>) -> FlatCommit {
+        FlatCommit {
+            schema_version: row.schema_version,
+            record_kind: row.record_kind.map(String::from),
+            id: row.id.to_string(),
+            short_id: row.short_id.to_string(),
+            tree_id: row.tree_id.map(String::from),
+            repo_url: row.repo_url.to_string(),
+            repo_root: row.repo_root.map(String::from),
+            seq: row.seq,
+            timestamp: row.timestamp,
+            author_tz_offset_minutes: row.author_tz_offset_minutes,
+            committer_tz_offset_minutes: row.committer_tz_offset_minutes,
+            author_name: row.author_name.map(String::from),
+            author_email: row.author_email.map(String::from),
+            message: row.message.to_string(),
+            subject: row.subject.map(String::from),
+            body: row.body.map(String::from),
+            message_length: row.message_length,
+            encoding: row.encoding.map(String::from),
+            r#type: row.r#type.clone(),
+            is_merge: row.is_merge,
+            squash: row.squash,
+            pr_number: row.pr_number,
+            parent_count: row.parent_count,
+            is_root: row.is_root,
+            shallow: row.shallow,
+            is_working: row.is_working,
+            branches: row.branches.map(<[String]>::to_vec),
+            record_hash: row.record_hash,
+            path: row.path.to_string(),
+            old_path: row.old_path.map(String::from),
+            status: row.status,
+            similarity: row.similarity,
+            language: row.language.map(String::from),
+            lines_added: row.lines_added,
+            lines_removed: row.lines_removed,
+            lines_modified: row.lines_modified,
+            hunks_added: row.hunks_added,
+            hunks_removed: row.hunks_removed,
+            hunks_modified: row.hunks_modified,
+            additions_in_modified_hunks: row.additions_in_modified_hunks,
+            deletions_in_modified_hunks: row.deletions_in_modified_hunks,
+            words_added: row.words_added,
+            words_removed: row.words_removed,
+            binary: row.binary,
+            whitespace_only: row.whitespace_only,
+            submodule_old_id: row.submodule_old_id.map(String::from),
+            submodule_new_id: row.submodule_new_id.map(String::from),
+            lfs: row.lfs,
+            lfs_size: row.lfs_size,
+            old_size: row.old_size,
+            new_size: row.new_size,
+            hunks: row.hunks.map(<[Hunk]>::to_vec),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::diff::ChangeStatus;
+
+    pub(crate) fn commit_changing(paths: &[&str]) -> Commit {
+        Commit {
+            id: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string(),
+            message: "Change some files".to_string(),
+            branches: Some(vec!["main".to_string()]),
+            changes: paths
+                .iter()
+                .map(|path| FileChange {
+                    path: path.to_string(),
+                    status: ChangeStatus::Added,
+                    lines_added: 1,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flat_gives_a_row_per_file_changed() {
+        let commit = commit_changing(&["a.txt", "b.txt"]);
+
+        let rows: Vec<FlatCommitRef> = commit.flat().collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].path, "b.txt");
+        assert_eq!(rows[1].id, commit.id);
+        assert_eq!(rows[1].branches, Some(&["main".to_string()][..]));
+    }
+
+    #[test]
+    fn owned_rows_serialize_the_same_as_borrowed_ones() {
+        let commit = commit_changing(&["a.txt"]);
+        let row = commit.flat().next().unwrap();
+        let borrowed = serde_json::to_string(&row).unwrap();
+
+        let owned = FlatCommit::from(row);
+        assert_eq!(serde_json::to_string(&owned).unwrap(), borrowed);
+
+        // And reads back in from what's written
+        //
+        let read: FlatCommit = serde_json::from_str(&borrowed).unwrap();
+        assert_eq!(serde_json::to_string(&read).unwrap(), borrowed);
+    }
+
+    #[test]
+    fn each_row_has_a_hash_of_its_own() {
+        let mut commit = commit_changing(&["a.txt", "b.txt"]);
+        commit.record_hash = Some(hash_record(&commit).unwrap());
+
+        let hashes = |commit: &Commit| -> Vec<String> {
+            commit.flat().map(|row| row.record_hash.unwrap()).collect()
+        };
+        let first = hashes(&commit);
+        assert_eq!(first, hashes(&commit.clone()));
+        assert_ne!(first[0], first[1]);
+        assert_ne!(first[0], *commit.record_hash.as_ref().unwrap());
+
+        // Where the row is in the run doesn't count, what's in it does
+        //
+        let mut moved = commit.clone();
+        moved.seq = Some(7);
+        assert_eq!(hashes(&moved), first);
+        let mut edited = commit.clone();
+        edited.changes[1].lines_added = 2;
+        let edited = hashes(&edited);
+        assert_eq!(edited[0], first[0]);
+        assert_ne!(edited[1], first[1]);
+
+        // The same as an owned row's
+        //
+        let row = FlatCommit::from(commit.flat().next().unwrap());
+        assert_eq!(hash_record(&row).unwrap(), first[0]);
+
+        commit.record_hash = None;
+        assert!(commit.flat().all(|row| row.record_hash.is_none()));
+    }
+}
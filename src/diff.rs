@@ -0,0 +1,639 @@
+use crate::{
+    attributes::{AttrValue, Attributes},
+    error::Error,
+};
+use git2::{Delta, Diff, DiffFile, DiffFormat, DiffOptions, FileMode, Repository};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+// Which of the file change fields that cost extra to work out are filled in - each
+// is left null (or its counts 0) otherwise. Everything else is always counted
+//
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiffSettings {
+    // similarity, for the renames and copies Diff::find_similar found
+    pub attribute_renames: bool,
+    // submodule_old_id and submodule_new_id
+    pub submodule_changes: bool,
+    // old_size and new_size
+    pub with_sizes: bool,
+    // words_added and words_removed
+    pub word_diff: bool,
+    // hunks
+    pub with_hunks: bool,
+    // additions_in_modified_hunks and deletions_in_modified_hunks
+    pub modified_hunk_lines: bool,
+}
+
+// What happened to a file - mirrors git2's Delta
+//
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeStatus {
+    Unmodified,
+    Added,
+    Deleted,
+    #[default]
+    Modified,
+    Renamed,
+    Copied,
+    Ignored,
+    Untracked,
+    Typechange,
+    Unreadable,
+    Conflicted,
+}
+
+impl From<Delta> for ChangeStatus {
+    fn from(delta: Delta) -> ChangeStatus {
+        match delta {
+            Delta::Unmodified => ChangeStatus::Unmodified,
+            Delta::Added => ChangeStatus::Added,
+            Delta::Deleted => ChangeStatus::Deleted,
+            Delta::Modified => ChangeStatus::Modified,
+            Delta::Renamed => ChangeStatus::Renamed,
+            Delta::Copied => ChangeStatus::Copied,
+            Delta::Ignored => ChangeStatus::Ignored,
+            Delta::Untracked => ChangeStatus::Untracked,
+            Delta::Typechange => ChangeStatus::Typechange,
+            Delta::Unreadable => ChangeStatus::Unreadable,
+            Delta::Conflicted => ChangeStatus::Conflicted,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct FileChange {
+    pub path: String,
+    pub old_path: Option<String>,
+    pub status: ChangeStatus,
+    pub similarity: Option<u8>,
+    pub language: Option<String>,
+    pub lines_added: u32,
+    pub lines_removed: u32,
+    pub lines_modified: u32,
+    pub hunks_added: u32,
+    pub hunks_removed: u32,
+    pub hunks_modified: u32,
+    pub additions_in_modified_hunks: Option<u32>,
+    pub deletions_in_modified_hunks: Option<u32>,
+    pub words_added: Option<u32>,
+    pub words_removed: Option<u32>,
+    pub binary: bool,
+    pub whitespace_only: bool,
+    pub submodule_old_id: Option<String>,
+    pub submodule_new_id: Option<String>,
+    pub lfs: bool,
+    pub lfs_size: Option<u64>,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    pub hunks: Option<Vec<Hunk>>,
+}
+
+// Where a single hunk landed, in both the old and new versions of the file
+//
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Hunk {
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+}
+
+impl FileChange {
+    // Folds a second entry for the same path into this one. Counts are summed and
+    // anything describing the old side of the file is taken from this (the first)
+    // entry, the new side from the other. Type changes are reported as such, but a
+    // delete and an add of the same path would still amount to one, so that's what
+    // those combine to
+    //
+    pub fn merge(self, other: FileChange) -> FileChange {
+        let status = match (self.status, other.status) {
+            (a, b) if a == b => a,
+            (ChangeStatus::Deleted, ChangeStatus::Added)
+            | (ChangeStatus::Added, ChangeStatus::Deleted) => ChangeStatus::Typechange,
+            _ => ChangeStatus::Modified,
+        };
+        let hunks = match (self.hunks, other.hunks) {
+            (Some(mut hunks), Some(other_hunks)) => {
+                hunks.extend(other_hunks);
+                Some(hunks)
+            }
+            (hunks, other_hunks) => hunks.or(other_hunks),
+        };
+
+        FileChange {
+            path: self.path,
+            old_path: self.old_path.or(other.old_path),
+            status,
+            similarity: self.similarity.or(other.similarity),
+            language: self.language.or(other.language),
+            lines_added: self.lines_added + other.lines_added,
+            lines_removed: self.lines_removed + other.lines_removed,
+            lines_modified: self.lines_modified + other.lines_modified,
+            hunks_added: self.hunks_added + other.hunks_added,
+            hunks_removed: self.hunks_removed + other.hunks_removed,
+            hunks_modified: self.hunks_modified + other.hunks_modified,
+            additions_in_modified_hunks: sum_counts(
+                self.additions_in_modified_hunks,
+                other.additions_in_modified_hunks,
+            ),
+            deletions_in_modified_hunks: sum_counts(
+                self.deletions_in_modified_hunks,
+                other.deletions_in_modified_hunks,
+            ),
+            words_added: sum_counts(self.words_added, other.words_added),
+            words_removed: sum_counts(self.words_removed, other.words_removed),
+            binary: self.binary || other.binary,
+            whitespace_only: self.whitespace_only && other.whitespace_only,
+            submodule_old_id: self.submodule_old_id.or(other.submodule_old_id),
+            submodule_new_id: other.submodule_new_id.or(self.submodule_new_id),
+            lfs: self.lfs || other.lfs,
+            lfs_size: other.lfs_size.or(self.lfs_size),
+            old_size: self.old_size.or(other.old_size),
+            new_size: other.new_size.or(self.new_size),
+            hunks,
+        }
+    }
+}
+
+fn sum_counts(a: Option<u32>, b: Option<u32>) -> Option<u32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    }
+}
+
+// Without include_typechange a file becoming a symlink (or the reverse) is split
+// into a delete and an add of the same path
+//
+pub fn diff_options() -> DiffOptions {
+    let mut options = DiffOptions::new();
+    options.include_typechange(true);
+    options
+}
+
+// A path from a tree as written to the output, with anything that isn't valid
+// UTF-8 replaced rather than panicking on it. git stores them / separated on every
+// OS, so a \ in one is part of a file name and is left alone
+//
+pub fn tree_path(path: &[u8]) -> String {
+    String::from_utf8_lossy(path).into_owned()
+}
+
+// The commit a submodule pointed at on one side of a delta, if that side is a
+// gitlink (and the submodule exists on that side at all)
+//
+fn submodule_id(file: &DiffFile) -> Option<String> {
+    if file.mode() == FileMode::Commit && !file.id().is_zero() {
+        Some(file.id().to_string())
+    } else {
+        None
+    }
+}
+
+// Git LFS keeps a small pointer file in the repo in place of the real content. We
+// never smudge these, but the pointer does tell us how big the real file is
+//
+fn lfs_pointer_size(repo: &Repository, file: &DiffFile) -> Option<u64> {
+    if file.id().is_zero() {
+        return None;
+    }
+    let blob = repo.find_blob(file.id()).ok()?;
+    let pointer = std::str::from_utf8(blob.content()).ok()?;
+
+    if !pointer.starts_with("version https://git-lfs.github.com/spec/") {
+        return None;
+    }
+    pointer
+        .lines()
+        .find_map(|line| line.strip_prefix("size "))
+        .and_then(|size| size.trim().parse().ok())
+}
+
+// Size in bytes of one side of a delta - 0 when the file doesn't exist on that side
+// (added / deleted) or isn't a blob at all (submodules)
+//
+fn blob_size(repo: &Repository, file: &DiffFile) -> u64 {
+    if file.id().is_zero() {
+        return 0;
+    }
+    repo.find_blob(file.id())
+        .map(|blob| blob.size() as u64)
+        .unwrap_or(0)
+}
+
+// Number of words common to both sides of an edit, in order - i.e. the length of
+// the longest common subsequence. Everything else was added or removed
+//
+fn common_words(removed: &[String], added: &[String]) -> u32 {
+    let mut previous = vec![0u32; added.len() + 1];
+    let mut current = vec![0u32; added.len() + 1];
+
+    for removed_word in removed {
+        for (i, added_word) in added.iter().enumerate() {
+            current[i + 1] = if removed_word == added_word {
+                previous[i] + 1
+            } else {
+                previous[i + 1].max(current[i])
+            };
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[added.len()]
+}
+
+// How similar (0-100) each rename or copy's source is to its new path, by the new
+// path. git2 doesn't expose a delta's similarity score, but libgit2's raw diff
+// format includes it after the status letter - the R080 of git diff --raw
+//
+fn rename_similarities(diff: &Diff) -> Result<HashMap<Vec<u8>, u8>, git2::Error> {
+    let mut similarities = HashMap::new();
+
+    diff.print(DiffFormat::Raw, |delta, _hunk, line| {
+        if !matches!(delta.status(), Delta::Renamed | Delta::Copied) {
+            return true;
+        }
+        let content = String::from_utf8_lossy(line.content());
+        let similarity = content
+            .split('\t')
+            .next()
+            .and_then(|fields| fields.split(' ').nth(4))
+            .and_then(|status| status.get(1..))
+            .and_then(|score| score.parse().ok());
+
+        if let (Some(similarity), Some(path)) = (similarity, delta.new_file().path_bytes()) {
+            similarities.insert(path.to_vec(), similarity);
+        }
+        true
+    })?;
+
+    Ok(similarities)
+}
+
+/// Counts up each file in a diff. The repo is the one the diff came from - blobs are
+/// looked up in it for LFS pointers and sizes. Attributes (the lfs filter and -diff)
+/// come from the given source, usually Attributes::from_tree on the diff's new tree.
+/// Make the diff with diff_options (plus Diff::find_similar for renames) to get the
+/// same file changes as the command line does
+///
+/// ```
+/// use git_fetch_commits::attributes::Attributes;
+/// use git_fetch_commits::diff::{changes_from_diff, diff_options, ChangeStatus, DiffSettings};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let repo = git2::Repository::init(dir.path())?;
+/// let tree = |files: &[(&str, &str)]| -> Result<git2::Oid, git2::Error> {
+///     let mut builder = repo.treebuilder(None)?;
+///     for (path, contents) in files {
+///         builder.insert(path, repo.blob(contents.as_bytes())?, 0o100644)?;
+///     }
+///     builder.write()
+/// };
+/// let old = repo.find_tree(tree(&[("a.txt", "one\ntwo\n")])?)?;
+/// let new = repo.find_tree(tree(&[("a.txt", "one\n2\nthree\n"), ("b.txt", "new\n")])?)?;
+///
+/// let diff = repo.diff_tree_to_tree(Some(&old), Some(&new), Some(&mut diff_options()))?;
+/// let attributes = Attributes::from_tree(&repo, &new);
+/// let changes = changes_from_diff(&repo, &diff, &attributes, &DiffSettings::default())?;
+///
+/// assert_eq!(changes.len(), 2);
+/// assert_eq!(changes[0].path, "a.txt");
+/// assert_eq!(changes[0].status, ChangeStatus::Modified);
+/// assert_eq!((changes[0].lines_added, changes[0].lines_removed), (2, 1));
+/// assert_eq!(changes[1].path, "b.txt");
+/// assert_eq!(changes[1].status, ChangeStatus::Added);
+/// assert_eq!((changes[1].lines_added, changes[1].lines_removed), (1, 0));
+/// # Ok(())
+/// # }
+/// ```
+pub fn changes_from_diff(
+    repo: &Repository,
+    diff: &Diff,
+    attributes: &Attributes,
+    settings: &DiffSettings,
+) -> Result<Vec<FileChange>, Error> {
+    // diff.foreach works in a very imperative way, looping through the diffs
+    // and calling callbacks in serial until it's complete
+    //
+    let mut files: Vec<FileChange> = Vec::new();
+
+    // Using a cell so we can modify the captured FileChange via the multiple closures below
+    // without ownership issues
+    //
+    let x: Cell<Option<FileChange>> = Cell::new(None);
+
+    let similarities = if settings.attribute_renames {
+        rename_similarities(diff)?
+    } else {
+        HashMap::new()
+    };
+
+    // Lines (added, removed) seen so far in the hunk currently being processed. A hunk
+    // can only be classified once all of its lines have been seen, i.e. when the next
+    // hunk or file starts, or the diff ends
+    //
+    let hunk_lines: Cell<Option<(u32, u32)>> = Cell::new(None);
+
+    // Whether the current file is marked -diff (or binary), so its hunks and lines
+    // are skipped whatever libgit2 made of them
+    //
+    let diff_unset: Cell<bool> = Cell::new(false);
+
+    // Words on the (removed, added) lines of the current hunk, only kept for word_diff
+    //
+    let hunk_words: RefCell<(Vec<String>, Vec<String>)> = RefCell::new((Vec::new(), Vec::new()));
+
+    // The (removed, added) lines of the current hunk with all their whitespace taken
+    // out, each ended with a newline - the same on both sides means the hunk only
+    // changed whitespace
+    //
+    let hunk_text: RefCell<(Vec<u8>, Vec<u8>)> = RefCell::new((Vec::new(), Vec::new()));
+
+    // Classify the finished hunk (if any) into the file's hunk counts:
+    //
+    //   - added lines but no removed lines => hunks_added
+    //   - removed lines but no added lines => hunks_removed
+    //   - anything else (both)             => hunks_modified
+    //
+    // The old_lines / new_lines in the hunk header can't be used for this as they
+    // include the context lines around the change, so a pure append to a file still
+    // has old_lines > 0
+    //
+    // With word_diff the words of the hunk's removed and added lines are also
+    // compared, counting the words that don't survive the edit. With
+    // modified_hunk_lines a modified hunk's lines are also added up on their own
+    //
+    let close_hunk = |state: FileChange| {
+        // A file is whitespace-only while each of its hunks is - so from the first
+        // hunk on, as a file without any hunks has no changed lines at all
+        //
+        let (removed_text, added_text) = hunk_text.take();
+        let state = match hunk_lines.get() {
+            None => state,
+            Some(_) => FileChange {
+                whitespace_only: (state.whitespace_only
+                    || state.hunks_added + state.hunks_removed + state.hunks_modified == 0)
+                    && removed_text == added_text,
+                ..state
+            },
+        };
+
+        let state = match hunk_lines.take() {
+            None => state,
+            Some((added, 0)) if added > 0 => FileChange {
+                hunks_added: state.hunks_added + 1,
+                ..state
+            },
+            Some((0, removed)) if removed > 0 => FileChange {
+                hunks_removed: state.hunks_removed + 1,
+                ..state
+            },
+            Some((added, removed)) => FileChange {
+                hunks_modified: state.hunks_modified + 1,
+                additions_in_modified_hunks: state
+                    .additions_in_modified_hunks
+                    .map(|additions| additions + added),
+                deletions_in_modified_hunks: state
+                    .deletions_in_modified_hunks
+                    .map(|deletions| deletions + removed),
+                ..state
+            },
+        };
+
+        let (removed_words, added_words) = hunk_words.take();
+        match (state.words_added, state.words_removed) {
+            (Some(words_added), Some(words_removed)) => {
+                let common = common_words(&removed_words, &added_words);
+                FileChange {
+                    words_added: Some(words_added + added_words.len() as u32 - common),
+                    words_removed: Some(words_removed + removed_words.len() as u32 - common),
+                    ..state
+                }
+            }
+            _ => state,
+        }
+    };
+
+    diff.foreach(
+        &mut |diff_delta, _s| {
+            // If we're currently busy with a file, this means we're moving on so we
+            // should push it and replace it with the new one
+            //
+            if let Some(file_change) = x.take() {
+                files.push(close_hunk(file_change));
+            }
+            let filename = tree_path(diff_delta.new_file().path_bytes().unwrap_or_default());
+
+            let (submodule_old_id, submodule_new_id) = if settings.submodule_changes {
+                (
+                    submodule_id(&diff_delta.old_file()),
+                    submodule_id(&diff_delta.new_file()),
+                )
+            } else {
+                (None, None)
+            };
+
+            // Paths tracked by LFS (filter=lfs in .gitattributes) are pointer files - take
+            // the size from the newest side of the pointer that still exists
+            //
+            let lfs = attributes.get(&filename, "filter") == Some(AttrValue::Value("lfs".into()));
+            let lfs_size = if lfs {
+                lfs_pointer_size(repo, &diff_delta.new_file())
+                    .or_else(|| lfs_pointer_size(repo, &diff_delta.old_file()))
+            } else {
+                None
+            };
+
+            let (old_size, new_size) = if settings.with_sizes {
+                (
+                    Some(blob_size(repo, &diff_delta.old_file())),
+                    Some(blob_size(repo, &diff_delta.new_file())),
+                )
+            } else {
+                (None, None)
+            };
+
+            // Only renames and copies have a different path on the old side
+            //
+            let old_path = diff_delta
+                .old_file()
+                .path_bytes()
+                .map(tree_path)
+                .filter(|old_filename| *old_filename != filename);
+
+            let similarity = matches!(diff_delta.status(), Delta::Renamed | Delta::Copied)
+                .then(|| {
+                    similarities
+                        .get(diff_delta.new_file().path_bytes().unwrap_or_default())
+                        .copied()
+                })
+                .flatten();
+
+            // A path marked -diff is binary however its content looks, the same as git
+            // log --numstat's "-" for it
+            //
+            diff_unset.set(attributes.get(&filename, "diff") == Some(AttrValue::Unset));
+
+            x.set(Some(FileChange {
+                path: filename,
+                old_path,
+                status: diff_delta.status().into(),
+                similarity,
+                submodule_old_id,
+                submodule_new_id,
+                binary: diff_unset.get(),
+                lfs,
+                lfs_size,
+                old_size,
+                new_size,
+                hunks: settings.with_hunks.then(Vec::new),
+                additions_in_modified_hunks: settings.modified_hunk_lines.then_some(0),
+                deletions_in_modified_hunks: settings.modified_hunk_lines.then_some(0),
+                words_added: settings.word_diff.then_some(0),
+                words_removed: settings.word_diff.then_some(0),
+                ..Default::default()
+            }));
+            true
+        },
+        // Binary files get no hunk / line callbacks so their counts stay at 0, the same
+        // as the "-" git log --numstat reports for them
+        //
+        Some(&mut |_diff_delta, _diff_binary| {
+            let state = x.take().unwrap();
+            x.set(Some(FileChange {
+                binary: true,
+                ..state
+            }));
+            true
+        }),
+        Some(&mut |diff_delta, diff_hunk| {
+            // The old and new sides of a type change (a file and a symlink's target,
+            // say) aren't versions of the same content, so comparing their lines
+            // means nothing - its counts are left at 0. Neither are a -diff path's
+            //
+            if diff_delta.status() == Delta::Typechange || diff_unset.get() {
+                return true;
+            }
+
+            // Guaranteed to be processing a file (big assumption?)
+            //
+            let mut updated = close_hunk(x.take().unwrap());
+            hunk_lines.set(Some((0, 0)));
+
+            if let Some(hunks) = updated.hunks.as_mut() {
+                hunks.push(Hunk {
+                    old_start: diff_hunk.old_start(),
+                    old_lines: diff_hunk.old_lines(),
+                    new_start: diff_hunk.new_start(),
+                    new_lines: diff_hunk.new_lines(),
+                });
+            }
+            x.set(Some(updated));
+            true
+        }),
+        Some(&mut |diff_delta, _diff_hunk, diff_line| {
+            if diff_delta.status() == Delta::Typechange || diff_unset.get() {
+                return true;
+            }
+
+            // Guaranteed to be processing a file (big assumption?)
+            //
+            let state = x.take().unwrap();
+            let (hunk_added, hunk_removed) = hunk_lines.get().unwrap_or_default();
+
+            if settings.word_diff {
+                let content = String::from_utf8_lossy(diff_line.content());
+                let words = content.split_whitespace().map(|word| word.to_string());
+                let mut hunk_words = hunk_words.borrow_mut();
+                match diff_line.origin() {
+                    '-' => hunk_words.0.extend(words),
+                    '+' => hunk_words.1.extend(words),
+                    _ => {}
+                }
+            }
+
+            if matches!(diff_line.origin(), '-' | '+') {
+                let mut hunk_text = hunk_text.borrow_mut();
+                let text = match diff_line.origin() {
+                    '-' => &mut hunk_text.0,
+                    _ => &mut hunk_text.1,
+                };
+                text.extend(
+                    diff_line
+                        .content()
+                        .iter()
+                        .filter(|b| !b.is_ascii_whitespace()),
+                );
+                text.push(b'\n');
+            }
+
+            // Classify on the line's origin, the same as git's own +/- counts:
+            //
+            //   '+'  a line added
+            //   '-'  a line removed
+            //   ' '  an unchanged context line
+            //   '='  "\ No newline at end of file", where neither side has one
+            //   '>'  the same, for an old side without one (the new side adds it)
+            //   '<'  the same, for a new side without one (the new side drops it)
+            //
+            // The last three only say whether the last line ends in a newline - any
+            // change to that line is already there as its own '-' and '+' - so they
+            // aren't lines of the file and aren't counted. Headers ('F', 'H') and
+            // binary ('B') only come up when a diff is printed, never here
+            //
+            let updated = match diff_line.origin() {
+                '+' => {
+                    hunk_lines.set(Some((hunk_added + 1, hunk_removed)));
+                    FileChange {
+                        lines_added: state.lines_added + 1,
+                        ..state
+                    }
+                }
+                '-' => {
+                    hunk_lines.set(Some((hunk_added, hunk_removed + 1)));
+                    FileChange {
+                        lines_removed: state.lines_removed + 1,
+                        ..state
+                    }
+                }
+                ' ' => FileChange {
+                    lines_modified: state.lines_modified + 1,
+                    ..state
+                },
+                _ => state,
+            };
+            x.set(Some(updated));
+            true
+        }),
+    )?;
+
+    // The last file (and its last hunk) never sees another file start after it, so
+    // finish it off here
+    //
+    if let Some(file_change) = x.take() {
+        files.push(close_hunk(file_change));
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree_paths_keep_backslashes_and_replace_invalid_utf8() {
+        assert_eq!(tree_path(b"src/lib.rs"), "src/lib.rs");
+        assert_eq!(tree_path(b"dir\\name/file.txt"), "dir\\name/file.txt");
+        assert_eq!(
+            tree_path(b"mixed\\and/bad\xff.txt"),
+            "mixed\\and/bad\u{fffd}.txt"
+        );
+    }
+}
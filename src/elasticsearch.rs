@@ -0,0 +1,310 @@
+use crate::{commit::Commit, error::Error, sink::OutputSink};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+// Failed documents named in the error, beyond which they're only counted
+//
+const MAX_FAILURES_REPORTED: usize = 10;
+
+// Indexes records into Elasticsearch through the _bulk API, a batch of documents
+// per request. Document ids are derived from what the document is about, so
+// indexing the same history again overwrites rather than duplicates
+//
+pub struct ElasticsearchSink {
+    bulk_url: String,
+    index: String,
+    nested: bool,
+    batch_size: usize,
+    body: String,
+    documents: usize,
+}
+
+// A document's _id, from the repo and commit - plus the path for rows, as a row's
+// position in the commit moves with --sort-files and the path filters. The repo is
+// part of it as forks share commit ids. Hashed, as _id is limited to 512 bytes and
+// urls and paths have no limit
+//
+fn document_id(parts: &[&str]) -> String {
+    format!("{:x}", Sha256::digest(parts.join("\0")))
+}
+
+impl ElasticsearchSink {
+    pub fn new(url: &str, index: &str, nested: bool, batch_size: usize) -> ElasticsearchSink {
+        ElasticsearchSink {
+            bulk_url: format!("{}/_bulk", url.trim_end_matches('/')),
+            index: index.to_string(),
+            nested,
+            batch_size,
+            body: String::new(),
+            documents: 0,
+        }
+    }
+
+    fn add<T: Serialize>(&mut self, id: &str, document: &T) -> Result<(), Error> {
+        let action = json!({ "index": { "_index": self.index, "_id": id } });
+        let document = serde_json::to_string(document)
+            .map_err(|e| Error::serde(format!("Serde failed for document {}", id), e))?;
+
+        self.body.push_str(&action.to_string());
+        self.body.push('\n');
+        self.body.push_str(&document);
+        self.body.push('\n');
+        self.documents += 1;
+
+        if self.documents >= self.batch_size {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    // A bulk request succeeds as a whole even when some of its documents were
+    // rejected - those are only listed in the response's items
+    //
+    fn send(&mut self) -> Result<(), Error> {
+        if self.documents == 0 {
+            return Ok(());
+        }
+
+        let response: Value = ureq::post(&self.bulk_url)
+            .set("Content-Type", "application/x-ndjson")
+            .send_string(&self.body)
+            .map_err(|e| Error::output("Elasticsearch failed", e))?
+            .into_json()
+            .map_err(|e| Error::io("Elasticsearch response unreadable", e))?;
+        self.body.clear();
+        self.documents = 0;
+
+        if response["errors"] != Value::Bool(true) {
+            return Ok(());
+        }
+
+        let failures: Vec<String> = response["items"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|item| item.as_object()?.values().next())
+            .filter(|result| !result["error"].is_null())
+            .map(|result| {
+                format!(
+                    "{} ({}: {})",
+                    result["_id"].as_str().unwrap_or("unknown"),
+                    result["error"]["type"].as_str().unwrap_or("error"),
+                    result["error"]["reason"]
+                        .as_str()
+                        .unwrap_or("no reason given")
+                )
+            })
+            .collect();
+
+        let mut reported = failures
+            .iter()
+            .take(MAX_FAILURES_REPORTED)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ");
+        if failures.len() > MAX_FAILURES_REPORTED {
+            reported += &format!(" and {} more", failures.len() - MAX_FAILURES_REPORTED);
+        }
+
+        Err(Error::Output {
+            context: format!("Elasticsearch rejected {} document(s)", failures.len()),
+            source: reported.into(),
+        })
+    }
+}
+
+impl OutputSink for ElasticsearchSink {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        if self.nested {
+            return self.add(&document_id(&[&commit.repo_url, &commit.id]), commit);
+        }
+        for flat in commit.flat() {
+            let id = document_id(&[flat.repo_url, flat.id, flat.path]);
+            self.add(&id, &flat)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        self.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commit::tests::commit_changing;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread::JoinHandle,
+    };
+
+    // A _bulk endpoint answering each request in turn with the next of responses,
+    // giving back the body of each request once they've all been answered
+    //
+    fn bulk_server(responses: Vec<Value>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                assert_eq!(request_line.trim(), "POST /_bulk HTTP/1.1");
+
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+
+                let response = response.to_string();
+                write!(
+                    &stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    fn index_commit(sink: ElasticsearchSink, commit: &Commit) -> Result<(), Error> {
+        let mut sink: Box<dyn OutputSink> = Box::new(sink);
+        sink.write_commit(commit)?;
+        sink.finish()
+    }
+
+    #[test]
+    fn rows_are_sent_in_batches_with_ids_from_the_repo_commit_and_path() {
+        let ok = json!({"errors": false, "items": []});
+        let (url, server) = bulk_server(vec![ok.clone(), ok]);
+        let commit = commit_changing(&["a.txt", "b.txt", "c.txt"]);
+
+        index_commit(
+            ElasticsearchSink::new(&format!("{}/", url), "commits", false, 2),
+            &commit,
+        )
+        .unwrap();
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies.len(), 2);
+        let lines: Vec<Value> = bodies
+            .iter()
+            .flat_map(|body| body.lines())
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 6);
+        for (n, pair) in lines.chunks(2).enumerate() {
+            assert_eq!(pair[0]["index"]["_index"], "commits");
+            assert_eq!(
+                pair[0]["index"]["_id"],
+                document_id(&[&commit.repo_url, &commit.id, &commit.changes[n].path])
+            );
+            assert_eq!(pair[1]["path"], commit.changes[n].path);
+        }
+    }
+
+    #[test]
+    fn row_ids_stay_put_when_the_files_are_reordered() {
+        let ok = json!({"errors": false, "items": []});
+        let (url, server) = bulk_server(vec![ok.clone(), ok]);
+        let commit = commit_changing(&["a.txt", "b.txt"]);
+        let mut reordered = commit.clone();
+        reordered.changes.reverse();
+
+        index_commit(ElasticsearchSink::new(&url, "commits", false, 500), &commit).unwrap();
+        index_commit(
+            ElasticsearchSink::new(&url, "commits", false, 500),
+            &reordered,
+        )
+        .unwrap();
+
+        let ids = |body: &str| -> Vec<(String, String)> {
+            let lines: Vec<Value> = body
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            let mut ids: Vec<(String, String)> = lines
+                .chunks(2)
+                .map(|pair| {
+                    (
+                        pair[1]["path"].as_str().unwrap().to_string(),
+                        pair[0]["index"]["_id"].as_str().unwrap().to_string(),
+                    )
+                })
+                .collect();
+            ids.sort();
+            ids
+        };
+        let bodies = server.join().unwrap();
+        assert_eq!(ids(&bodies[0]), ids(&bodies[1]));
+    }
+
+    #[test]
+    fn nested_commits_are_indexed_by_their_repo_and_id() {
+        let (url, server) = bulk_server(vec![json!({"errors": false, "items": []})]);
+        let commit = commit_changing(&["a.txt", "b.txt"]);
+
+        index_commit(ElasticsearchSink::new(&url, "commits", true, 500), &commit).unwrap();
+
+        let bodies = server.join().unwrap();
+        let lines: Vec<Value> = bodies[0]
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0]["index"]["_id"],
+            document_id(&[&commit.repo_url, &commit.id])
+        );
+        assert_eq!(lines[1]["changes"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejected_documents_are_named_in_the_error() {
+        let commit = commit_changing(&["a.txt", "b.txt"]);
+        let (url, server) = bulk_server(vec![json!({
+            "errors": true,
+            "items": [
+                {"index": {"_id": "first", "status": 201}},
+                {"index": {"_id": "second", "status": 400, "error": {
+                    "type": "mapper_parsing_exception",
+                    "reason": "failed to parse field [lines_added]"
+                }}}
+            ]
+        })]);
+
+        let result = index_commit(ElasticsearchSink::new(&url, "commits", false, 500), &commit);
+
+        server.join().unwrap();
+        match result {
+            Err(error @ Error::Output { .. }) => assert_eq!(
+                error.to_string(),
+                format!(
+                    "Elasticsearch rejected 1 document(s): second (mapper_parsing_exception: failed to parse field [lines_added])"
+                )
+            ),
+            other => panic!("expected an Output error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::io;
+
+type Source = Box<dyn std::error::Error + Send + Sync>;
+
+// Everything that can go wrong, keeping hold of the underlying error so the real
+// cause isn't lost along the way
+//
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // Anything from libgit2 outside of cloning - opening, walking, diffing
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+
+    #[error("Clone of {url} failed: {source}")]
+    Clone {
+        url: String,
+        #[source]
+        source: git2::Error,
+    },
+
+    // Credentials or host keys that couldn't be supplied or were rejected
+    #[error("{0}")]
+    Auth(String),
+
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{context}: {source}")]
+    Serde {
+        context: String,
+        #[source]
+        source: Source,
+    },
+
+    // An output sink's own failure (a database or server rejecting rows)
+    #[error("{context}: {source}")]
+    Output {
+        context: String,
+        #[source]
+        source: Source,
+    },
+
+    // Options that don't make sense, or don't go together
+    #[error("{0}")]
+    Config(String),
+
+    // Extracted counts that contradict each other, caught by --strict
+    #[error("{0}")]
+    Invariant(String),
+}
+
+impl Error {
+    // Which of the above this is, for --emit-errors records
+    //
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Git(_) => "git",
+            Error::Clone { .. } => "clone",
+            Error::Auth(_) => "auth",
+            Error::Io { .. } => "io",
+            Error::Serde { .. } => "serde",
+            Error::Output { .. } => "output",
+            Error::Config(_) => "config",
+            Error::Invariant(_) => "invariant",
+        }
+    }
+
+    pub fn io(context: impl Into<String>, source: io::Error) -> Error {
+        Error::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn serde(
+        context: impl Into<String>,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Error {
+        Error::Serde {
+            context: context.into(),
+            source: Box::new(source),
+        }
+    }
+
+    pub fn output(
+        context: impl Into<String>,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Error {
+        Error::Output {
+            context: context.into(),
+            source: Box::new(source),
+        }
+    }
+}
@@ -0,0 +1,1678 @@
+use crate::{
+    attributes::Attributes,
+    auth::{certificate_check, credentials, AuthAttempts},
+    cache::DiffCache,
+    commit::{hash_record, Commit, CommitType},
+    diff::{changes_from_diff, diff_options, DiffSettings, FileChange},
+    error::Error,
+    language,
+    progress::{progress_style, Progress},
+    settings::{DateSource, OnMissing, ProgressFormat, Settings, TypeFilter},
+    sink::OutputSink,
+    SCHEMA_VERSION,
+};
+use byte_unit::{Byte, UnitType};
+use git2::{
+    BranchType, Diff, DiffFindOptions, ErrorCode, FetchOptions, Oid, RemoteCallbacks, Repository,
+    Sort, SubmoduleUpdateOptions,
+};
+use indicatif::{ProgressBar, TermLike};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tempfile::tempdir;
+
+// The id of the pseudo-commit --include-uncommitted writes for uncommitted changes
+//
+const WORKING_ID: &str = "WORKING";
+
+// A file with hunks has to have lines in them somewhere - anything else means the
+// diff callbacks have miscounted
+//
+fn check_line_counts(id: &str, changes: &[FileChange]) -> Result<(), Error> {
+    for change in changes {
+        let hunks = change.hunks_added + change.hunks_removed + change.hunks_modified;
+        let lines = change.lines_added + change.lines_removed + change.lines_modified;
+        if hunks > 0 && lines == 0 {
+            return Err(Error::Invariant(format!(
+                "Commit {} file {} has {} hunk(s) but no lines added, removed or modified",
+                id, change.path, hunks
+            )));
+        }
+    }
+    Ok(())
+}
+
+// The hash of a commit's nested record - flat rows each hash their own
+//
+fn record_hash(commit: &Commit) -> Result<String, Error> {
+    hash_record(commit)
+        .map_err(|e| Error::serde(format!("Serde failed for commit {}", commit.id), e))
+}
+
+fn diff_settings(settings: &Settings) -> DiffSettings {
+    DiffSettings {
+        attribute_renames: settings.attribute_renames,
+        submodule_changes: settings.submodule_changes,
+        with_sizes: settings.with_sizes,
+        word_diff: settings.word_diff,
+        with_hunks: settings.with_hunks,
+        modified_hunk_lines: settings.with_modified_hunk_lines,
+    }
+}
+
+fn find_similar(diff: &mut Diff, settings: &Settings) -> Result<(), git2::Error> {
+    if settings.find_renames || settings.find_copies {
+        let mut find_options = DiffFindOptions::new();
+        find_options
+            .renames(true)
+            .copies(settings.find_copies)
+            .rename_threshold(settings.rename_threshold);
+        diff.find_similar(Some(&mut find_options))?;
+    }
+    Ok(())
+}
+
+// What's done to a commit's changes once they've been extracted (or taken from the
+// cache). The language is worked out from the path alone, so it's cheap enough to
+// do here rather than keeping it in the diff cache
+//
+fn tidy_changes(mut changes: Vec<FileChange>, settings: &Settings) -> Vec<FileChange> {
+    // A path's depth is the number of directories it's in - 0 at the top level
+    //
+    if let Some(max_path_depth) = settings.max_path_depth {
+        changes.retain(|change| change.path.matches('/').count() <= max_path_depth as usize);
+    }
+    if !settings.ext.is_empty() {
+        changes.retain(|change| wanted_extension(&change.path, &settings.ext));
+    }
+    if settings.detect_language {
+        for change in changes.iter_mut() {
+            change.language = language::detect(&change.path).map(String::from);
+        }
+    }
+    if settings.merge_duplicate_paths {
+        changes = merge_duplicate_paths(changes);
+    }
+    if settings.sort_files {
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    changes
+}
+
+// Whether a path's extension is one of --ext's, ignoring case. A file without one
+// (dotfiles included, .gitignore having no extension) matches an empty entry
+//
+fn wanted_extension(path: &str, extensions: &[String]) -> bool {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .map_or(String::new(), |extension| {
+            extension.to_string_lossy().to_lowercase()
+        });
+    extensions
+        .iter()
+        .any(|wanted| wanted.trim_start_matches('.').to_lowercase() == extension)
+}
+
+// Coalesces entries sharing a path into one, keeping the order paths first appear in
+//
+fn merge_duplicate_paths(changes: Vec<FileChange>) -> Vec<FileChange> {
+    let mut merged: Vec<FileChange> = Vec::with_capacity(changes.len());
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for change in changes {
+        match positions.get(&change.path) {
+            Some(&position) => {
+                let existing = std::mem::take(&mut merged[position]);
+                merged[position] = existing.merge(change);
+            }
+            None => {
+                positions.insert(change.path.clone(), merged.len());
+                merged.push(change);
+            }
+        }
+    }
+    merged
+}
+
+// Initialise and update every submodule (and theirs in turn), using the same
+// credentials as the main clone
+//
+fn update_submodules(settings: &Settings, repo: &Repository) -> Result<(), git2::Error> {
+    for mut submodule in repo.submodules()? {
+        let mut callbacks = RemoteCallbacks::new();
+        let attempts = AuthAttempts::default();
+        callbacks.credentials(move |url, username_from_url, allowed_types| {
+            credentials(settings, &attempts, url, username_from_url, allowed_types)
+        });
+        let url = submodule.url().unwrap_or_default().to_string();
+        callbacks
+            .certificate_check(move |cert, host| certificate_check(settings, &url, cert, host));
+
+        let mut fo = FetchOptions::new();
+        fo.remote_callbacks(callbacks);
+
+        let mut update_options = SubmoduleUpdateOptions::new();
+        update_options.fetch(fo);
+
+        submodule.update(true, Some(&mut update_options))?;
+        update_submodules(settings, &submodule.open()?)?;
+    }
+    Ok(())
+}
+
+// A signature's name or email as it's to be written. Missing covers empty as well
+// as not there at all (or not UTF-8, which git2 can't hand over), and None means
+// the commit is skipped
+//
+fn signature_field(value: Option<&str>, on_missing: OnMissing) -> Option<Option<String>> {
+    match value.filter(|value| !value.is_empty()) {
+        Some(value) => Some(Some(value.to_string())),
+        None => match on_missing {
+            OnMissing::Placeholder => Some(Some("unknown".to_string())),
+            OnMissing::Null => Some(None),
+            OnMissing::Skip => None,
+        },
+    }
+}
+
+// Whether a commit looks like a squash merge - one commit standing in for a whole
+// pull request, going by the PR number GitHub puts at the end of its subject
+// ("Fix the thing (#123)"). The pattern's first group, if it has one, is the
+// number. Null without --detect-squash
+//
+fn squash_merge(settings: &Settings, message: &str, is_merge: bool) -> (Option<bool>, Option<u64>) {
+    if !settings.detect_squash {
+        return (None, None);
+    }
+    let subject = message.lines().next().unwrap_or_default();
+    match settings
+        .squash_pattern
+        .captures(subject)
+        .filter(|_| !is_merge)
+    {
+        Some(captures) => (
+            Some(true),
+            captures
+                .get(1)
+                .and_then(|number| number.as_str().parse().ok()),
+        ),
+        None => (Some(false), None),
+    }
+}
+
+// A commit message's subject and body, the way git splits them - the subject is
+// the first paragraph (normally a single line), the body everything after the
+// blank line that ends it. The body is empty when there's nothing after it
+//
+fn split_message(message: &str) -> (String, String) {
+    let message = message.trim_start_matches(['\r', '\n']);
+    let mut lines = message.lines();
+
+    let subject: Vec<&str> = lines
+        .by_ref()
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect();
+    let body: Vec<&str> = lines.collect();
+
+    (subject.join(" "), body.join("\n").trim().to_string())
+}
+
+// What a repo's records give as their repo_url - the URL it was cloned from, unless
+// that's been relabelled or --remote names the remote it came from
+//
+pub fn repo_label<'a>(settings: &'a Settings, repo_url: &'a str) -> Cow<'a, str> {
+    match (settings.repo_label.as_deref(), settings.remote.as_deref()) {
+        (Some(label), _) => Cow::Borrowed(label),
+        (None, Some(remote)) => {
+            remote_url(repo_url, remote).map_or(Cow::Borrowed(repo_url), Cow::Owned)
+        }
+        (None, None) => Cow::Borrowed(repo_url),
+    }
+}
+
+// The URL of one of a local repo's remotes
+//
+fn remote_url(repo_url: &str, remote: &str) -> Result<String, Error> {
+    let source = match local_repo_path(repo_url).map(Repository::open) {
+        Some(source) => source?,
+        None => {
+            return Err(Error::Config(format!(
+                "--remote needs a local repo, and {} isn't one",
+                repo_url
+            )))
+        }
+    };
+    let remote = source
+        .find_remote(remote)
+        .map_err(|_e| Error::Config(format!("{} has no remote named {}", repo_url, remote)))?;
+    Ok(remote.url().unwrap_or(repo_url).to_string())
+}
+
+// The commit a revision names in the clone - anything git rev-parse takes (a tag,
+// an id, HEAD~3). A branch other than the default one is only in the clone as a
+// remote-tracking branch, so a plain branch name is looked for there too
+//
+fn resolve_revision(
+    repo: &Repository,
+    settings: &Settings,
+    rev: &str,
+    flag: &str,
+    repo_url: &str,
+) -> Result<Oid, Error> {
+    let remote = settings.remote.as_deref().unwrap_or("origin");
+    repo.revparse_single(rev)
+        .or_else(|_e| repo.revparse_single(&format!("{}/{}", remote, rev)))
+        .and_then(|object| object.peel_to_commit())
+        .map(|commit| commit.id())
+        .map_err(|_e| {
+            Error::Config(format!(
+                "{} {} doesn't name a commit in {}",
+                flag, rev, repo_url
+            ))
+        })
+}
+
+// Swaps the clone's remote-tracking branches - the local repo's own branches, as
+// origin/* - for those of one of the local repo's remotes, as of its last fetch.
+// The remote's HEAD comes along when the local repo knows it, as it would from
+// cloning the remote itself
+//
+fn track_remote(repo: &Repository, repo_url: &str, remote: &str) -> Result<(), Error> {
+    let origin_refs = repo
+        .references_glob("refs/remotes/origin/*")?
+        .filter_map(|reference| Some(reference.ok()?.name()?.to_string()))
+        .collect::<Vec<_>>();
+    for name in origin_refs {
+        repo.find_reference(&name)?.delete()?;
+    }
+
+    let refspec = format!("+refs/remotes/{0}/*:refs/remotes/{0}/*", remote);
+    repo.remote_anonymous(repo_url)?
+        .fetch(&[refspec], None, None)?;
+
+    // The fetch leaves HEAD pointing straight at a commit, where it should name the
+    // default branch
+    //
+    let head = format!("refs/remotes/{}/HEAD", remote);
+    let _ = repo
+        .find_reference(&head)
+        .and_then(|mut head| head.delete());
+    let source = Repository::open(repo_url.strip_prefix("file://").unwrap_or(repo_url))?;
+    if let Some(target) = source
+        .find_reference(&head)
+        .ok()
+        .and_then(|head| head.symbolic_target().map(String::from))
+    {
+        repo.reference_symbolic(&head, &target, true, "remote HEAD")?;
+    }
+    Ok(())
+}
+
+// The path of a repo given as a local path or file:// URL
+//
+fn local_repo_path(repo_url: &str) -> Option<&std::path::Path> {
+    let path = std::path::Path::new(repo_url.strip_prefix("file://").unwrap_or(repo_url));
+    path.exists().then_some(path)
+}
+
+// Where a local repo lives - its worktree, or the git dir itself when it's bare.
+// Several local repos can share a URL-ish name (two checkouts both given as ".")
+// so this is what tells them apart
+//
+fn local_repo_root(repo_url: &str) -> Option<String> {
+    let source = Repository::open(local_repo_path(repo_url)?).ok()?;
+    let root = source.workdir().unwrap_or(source.path());
+    let root = root.canonicalize().unwrap_or_else(|_e| root.to_path_buf());
+    Some(
+        slash_separated(&root.to_string_lossy(), std::path::MAIN_SEPARATOR)
+            .trim_end_matches('/')
+            .to_string(),
+    )
+}
+
+// A path on this machine written / separated like the paths from git, whatever
+// separator the OS uses
+//
+fn slash_separated(path: &str, separator: char) -> String {
+    path.replace(separator, "/")
+}
+
+// Which branches each commit is on, by name - every branch in the clone, whether
+// or not it's being walked. Costs a walk of each branch's history
+//
+fn branch_membership(
+    repo: &Repository,
+    mirror: bool,
+) -> Result<HashMap<Oid, Vec<String>>, git2::Error> {
+    // A mirror's branches are local, anything else has them as origin/*
+    //
+    let branch_type = if mirror {
+        BranchType::Local
+    } else {
+        BranchType::Remote
+    };
+    let mut membership: HashMap<Oid, Vec<String>> = HashMap::new();
+
+    for (branch, _branch_type) in repo.branches(Some(branch_type))?.flatten() {
+        // origin/HEAD is only a pointer to the default branch, with no target of
+        // its own
+        //
+        let (name, target) = match (branch.name()?, branch.get().target()) {
+            (Some(name), Some(target)) => (name, target),
+            _ => continue,
+        };
+        let name = match name.split_once('/') {
+            Some((_remote, name)) if !mirror => name,
+            _ => name,
+        };
+
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(target)?;
+        for oid in revwalk {
+            membership.entry(oid?).or_default().push(name.to_string());
+        }
+    }
+
+    for names in membership.values_mut() {
+        names.sort();
+    }
+    Ok(membership)
+}
+
+// The changes not yet committed to a local repo - HEAD against its index and
+// worktree together, the same as git diff HEAD, so untracked files aren't included.
+// The clone doesn't have them, so this goes to the repo itself. None for remote
+// and bare repos, and when there's nothing uncommitted
+//
+fn working_commit(
+    settings: &Settings,
+    repo_url: &str,
+    repo_root: Option<String>,
+) -> Result<Option<Commit>, Error> {
+    let source = match local_repo_path(repo_url).map(Repository::open) {
+        Some(Ok(source)) if !source.is_bare() => source,
+        _ => return Ok(None),
+    };
+
+    // An unborn HEAD (nothing committed yet) has everything still to come
+    //
+    let head = source.head().ok();
+    let head_tree = head.as_ref().map(|head| head.peel_to_tree()).transpose()?;
+
+    let mut diff =
+        source.diff_tree_to_workdir_with_index(head_tree.as_ref(), Some(&mut diff_options()))?;
+    find_similar(&mut diff, settings)?;
+    let changes = changes_from_diff(
+        &source,
+        &diff,
+        &Attributes::worktree(&source),
+        &diff_settings(settings),
+    )?;
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    if settings.strict {
+        check_line_counts(WORKING_ID, &changes)?;
+    }
+
+    // The signature is stamped with the current time, in the local timezone
+    //
+    let signature = source.signature().ok();
+    let (author_name, author_email) = match (
+        signature_field(
+            signature.as_ref().and_then(|s| s.name()),
+            settings.on_missing_name,
+        ),
+        signature_field(
+            signature.as_ref().and_then(|s| s.email()),
+            settings.on_missing_email,
+        ),
+    ) {
+        (Some(name), Some(email)) => (name, email),
+        _ => return Ok(None),
+    };
+    let tz_offset_minutes = signature.map_or(0, |s| s.when().offset_minutes());
+    let branch = head
+        .as_ref()
+        .filter(|head| head.is_branch())
+        .and_then(|head| head.shorthand())
+        .map(String::from);
+
+    Ok(Some(Commit {
+        schema_version: SCHEMA_VERSION,
+        record_kind: settings.emit_errors.then_some("commit"),
+        id: WORKING_ID.to_string(),
+        short_id: WORKING_ID.to_string(),
+        tree_id: None,
+        repo_url: repo_label(settings, repo_url).to_string(),
+        repo_root,
+        seq: None,
+        timestamp: unix_time(),
+        author_tz_offset_minutes: tz_offset_minutes,
+        committer_tz_offset_minutes: tz_offset_minutes,
+        author_name,
+        author_email,
+        message: String::new(),
+        subject: settings.split_message.then(String::new),
+        body: settings.split_message.then(String::new),
+        message_length: settings.split_message.then_some(0),
+        encoding: None,
+        r#type: CommitType::Normal,
+        is_merge: false,
+        squash: settings.detect_squash.then_some(false),
+        pr_number: None,
+        parent_count: u32::from(head_tree.is_some()),
+        is_root: head_tree.is_none(),
+        shallow: false,
+        is_working: true,
+        branches: settings.with_branches.then(|| branch.into_iter().collect()),
+        record_hash: None,
+        changes: tidy_changes(changes, settings),
+    }))
+}
+
+// Puts the walk in committer time order with ties broken by commit id - the walk
+// itself leaves commits made in the same second in whatever order it came across
+// them. Ids are ascending either way round, so a tie reads the same both ways
+//
+fn stable_order(repo: &Repository, oids: &mut [Oid], chronological: bool) -> Result<(), Error> {
+    let mut times = HashMap::new();
+    for oid in oids.iter() {
+        times.insert(*oid, repo.find_commit(*oid)?.time().seconds());
+    }
+    oids.sort_by(|a, b| {
+        let by_time = times[a].cmp(&times[b]);
+        let by_time = if chronological {
+            by_time
+        } else {
+            by_time.reverse()
+        };
+        by_time.then_with(|| a.cmp(b))
+    });
+    Ok(())
+}
+
+// The commits already written by the run being resumed that everything before was
+// written too - hiding them from the walk (which hides all of their history) then
+// loses nothing. That's everything written by an interrupted --chronological run.
+// Newest first, a run is interrupted before it gets to the old commits, so none of
+// what it wrote can be hidden and each one's skipped by id instead
+//
+fn fully_emitted(
+    repo: &Repository,
+    emitted: &HashSet<String>,
+) -> Result<HashSet<Oid>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    for id in emitted {
+        // The WORKING pseudo-commit isn't one, and commits since rewritten out of
+        // the history may not be there any more
+        //
+        if let Ok(oid) = Oid::from_str(id) {
+            if repo.find_commit(oid).is_ok() {
+                revwalk.push(oid)?;
+            }
+        }
+    }
+
+    // Parents come before their children, so each commit's parents are settled by
+    // the time it's reached
+    //
+    let mut complete = HashSet::new();
+    for oid in revwalk {
+        let oid = oid?;
+        let commit = repo.find_commit(oid)?;
+        if emitted.contains(&oid.to_string())
+            && commit.parent_ids().all(|parent| complete.contains(&parent))
+        {
+            complete.insert(oid);
+        }
+    }
+    Ok(complete)
+}
+
+// The commits a shallow repo's history is cut off at - their parents were never
+// fetched, so they look like root commits
+//
+fn shallow_commits(repo: &Repository) -> Result<HashSet<Oid>, git2::Error> {
+    if !repo.is_shallow() {
+        return Ok(HashSet::new());
+    }
+    let contents = std::fs::read_to_string(repo.path().join("shallow"))
+        .map_err(|e| git2::Error::from_str(&format!("Shallow read failed! {}", e)))?;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Oid::from_str(line.trim()))
+        .collect()
+}
+
+// How long to hold off so that bytes received over elapsed stays within
+// max_bandwidth (bytes per second) - None while it's already under
+//
+fn throttle_delay(bytes: u64, elapsed: Duration, max_bandwidth: u64) -> Option<Duration> {
+    if max_bandwidth == 0 {
+        return None;
+    }
+    let allowed = Duration::from_secs_f64(bytes as f64 / max_bandwidth as f64);
+    allowed
+        .checked_sub(elapsed)
+        .filter(|delay| !delay.is_zero())
+}
+
+// A clone progress update as written by --progress-format json
+//
+#[derive(Serialize)]
+struct ProgressEvent<'a> {
+    repo_url: &'a str,
+    phase: &'a str,
+    received_objects: usize,
+    total_objects: usize,
+    indexed_objects: usize,
+    bytes: usize,
+}
+
+// JSON progress is written at most this often, apart from the final update
+//
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_millis(250);
+
+fn extract_logs(
+    settings: &Settings,
+    repo_url: &str,
+    emitted: Option<&HashSet<String>>,
+    progress_output: &Progress,
+    sink: &mut dyn OutputSink,
+) -> Result<RepoStats, Error> {
+    let multiprogress = &progress_output.multiprogress;
+    let last_progress_event: Cell<Option<Instant>> = Cell::new(None);
+    // The bars only show the bytes received while the clone is going - this keeps
+    // the final total for the summary
+    //
+    let clone_bytes: Cell<usize> = Cell::new(0);
+    let transfer_started = Instant::now();
+
+    let sty = progress_style(settings).map_err(|e| Error::Config(e.to_string()))?;
+
+    let progress_client = multiprogress.add(ProgressBar::new(1));
+    let progress_server = multiprogress.add(ProgressBar::new(1));
+    let progress_transfer = multiprogress.add(ProgressBar::new(1));
+    let progress_indexing = multiprogress.add(ProgressBar::new(1));
+    progress_client.set_style(sty.clone());
+    progress_client.set_message("Client progress");
+    progress_server.set_style(sty.clone());
+    progress_server.set_message("Server progress");
+    progress_transfer.set_style(sty.clone());
+    progress_transfer.set_message("Transfer progress");
+    progress_indexing.set_style(sty.clone());
+    progress_indexing.set_message("Indexing progress");
+
+    // Why the credentials or host key were turned down, if they were - libgit2 only
+    // passes on a failed clone, so this is what tells an auth failure apart
+    //
+    let auth_failure: RefCell<Option<String>> = RefCell::new(None);
+
+    let mut callbacks = RemoteCallbacks::new();
+
+    let attempts = AuthAttempts::default();
+    callbacks.credentials(|url, username_from_url, allowed_types| {
+        // eprintln!(
+        //     "Credentials callback for url={} username={} allowed={:?}",
+        //     url,
+        //     username_from_url.unwrap_or("none"),
+        //     allowed_types
+        // );
+
+        progress_client.set_message("Authenticating...");
+
+        // Keep the bars out of the way of any --interactive prompt
+        //
+        let result = multiprogress
+            .suspend(|| credentials(settings, &attempts, url, username_from_url, allowed_types));
+        if let Err(e) = &result {
+            *auth_failure.borrow_mut() = Some(e.message().to_string());
+        }
+        result
+    });
+
+    callbacks.certificate_check(|cert, host| {
+        let result = certificate_check(settings, repo_url, cert, host);
+        if let Err(e) = &result {
+            *auth_failure.borrow_mut() = Some(e.message().to_string());
+        }
+        result
+    });
+
+    callbacks.transfer_progress(|progress| {
+        if let Some(max_bandwidth) = settings.max_bandwidth {
+            if let Some(delay) = throttle_delay(
+                progress.received_bytes() as u64,
+                transfer_started.elapsed(),
+                max_bandwidth,
+            ) {
+                std::thread::sleep(delay);
+            }
+        }
+
+        clone_bytes.set(progress.received_bytes());
+        let received_byte = Byte::from_u128(u128::try_from(progress.received_bytes()).unwrap())
+            .unwrap()
+            .get_appropriate_unit(UnitType::Binary);
+
+        // eprintln!(
+        //     "Progress => Received {} of {}, indexed {}, bytes {}",
+        //     progress.received_objects(),
+        //     progress.total_objects(),
+        //     progress.indexed_objects(),
+        //     adjusted_byte.get_appropriate_unit(UnitType::Binary)
+        // );
+
+        progress_transfer.set_length(progress.total_objects() as u64);
+        progress_transfer.set_position(progress.received_objects() as u64);
+        progress_transfer.set_message(format!("Tranferred {received_byte:.2}"));
+
+        progress_indexing.set_length(progress.total_objects() as u64);
+        progress_indexing.set_position(progress.indexed_objects() as u64);
+
+        if let ProgressFormat::Json = settings.progress_format {
+            let done = progress.indexed_objects() == progress.total_objects();
+            let due = last_progress_event
+                .get()
+                .is_none_or(|last| last.elapsed() >= PROGRESS_EVENT_INTERVAL);
+
+            if done || due {
+                let event = ProgressEvent {
+                    repo_url,
+                    phase: if progress.received_objects() < progress.total_objects() {
+                        "receiving"
+                    } else {
+                        "indexing"
+                    },
+                    received_objects: progress.received_objects(),
+                    total_objects: progress.total_objects(),
+                    indexed_objects: progress.indexed_objects(),
+                    bytes: progress.received_bytes(),
+                };
+                if let Ok(json) = serde_json::to_string(&event) {
+                    match progress_output.json_file.as_ref() {
+                        Some(progress_file) => {
+                            let _ = progress_file.write_line(&json);
+                        }
+                        None => eprintln!("{}", json),
+                    }
+                }
+                last_progress_event.set(Some(Instant::now()));
+            }
+        }
+
+        true
+    });
+
+    callbacks.pack_progress(|pack_builder_stage, current, total| {
+        eprintln!(
+            "Packing => Stage {:?}, b {}, c {}",
+            pack_builder_stage, current, total
+        );
+    });
+
+    callbacks.sideband_progress(|sb| {
+        let msg = format!(
+            "Server: {}",
+            String::from_utf8_lossy(sb)
+                .to_string()
+                .replace("\n", "")
+                .replace("\r", "")
+        );
+        progress_server.set_message(msg);
+        true
+    });
+
+    let mut fo = git2::FetchOptions::new();
+    fo.remote_callbacks(callbacks);
+
+    let mut builder = git2::build::RepoBuilder::new();
+    builder.fetch_options(fo);
+
+    // A mirror is bare and fetches every ref as-is (not just branches), the same as
+    // git clone --mirror. There's no worktree to check out, which saves time and disk
+    //
+    if settings.mirror {
+        builder.bare(true);
+        builder.remote_create(|repo, name, url| {
+            let remote = repo.remote_with_fetch(name, url, "+refs/*:refs/*")?;
+            repo.config()?
+                .set_bool(&format!("remote.{}.mirror", name), true)?;
+            Ok(remote)
+        });
+    }
+    // Anything else gets a worktree checked out, and on Windows a path longer than
+    // 260 characters fails the checkout unless core.longpaths is set. Creating the
+    // remote is the one point between the clone's init and its checkout
+    //
+    else {
+        builder.remote_create(|repo, name, url| {
+            repo.config()?.set_bool("core.longpaths", true)?;
+            repo.remote(name, url)
+        });
+    }
+
+    // A remote that isn't there fails the repo before anything is cloned
+    //
+    if let Some(remote) = settings.remote.as_deref() {
+        remote_url(repo_url, remote)?;
+    }
+    let label = repo_label(settings, repo_url);
+
+    let temp_dir = tempdir().map_err(|e| Error::io("TempDir failed", e))?;
+    // eprintln!("Using tempdir => {}", temp_dir.path().to_str().unwrap());
+    progress_client.set_message("Cloning...");
+    let mut repo = builder.clone(repo_url, temp_dir.path()).map_err(|e| {
+        match auth_failure.borrow_mut().take() {
+            Some(reason) => Error::Auth(reason),
+            None if e.code() == ErrorCode::Auth => Error::Auth(e.message().to_string()),
+            None => Error::Clone {
+                url: repo_url.to_string(),
+                source: e,
+            },
+        }
+    })?;
+    progress_client.set_message("Clone complete");
+
+    if let Some(remote) = settings.remote.as_deref() {
+        track_remote(&repo, repo_url, remote)?;
+    }
+
+    // Cloning a shallow local repo doesn't bring its shallow list along, leaving a
+    // clone whose oldest commits point at parents that aren't there. Copying the
+    // list over makes the clone shallow in the same way, so the walk stops there
+    //
+    if let Some(source) = local_repo_path(repo_url).and_then(|p| Repository::open(p).ok()) {
+        if source.is_shallow() {
+            std::fs::copy(source.path().join("shallow"), repo.path().join("shallow"))
+                .map_err(|e| Error::io("Shallow copy failed", e))?;
+            repo = Repository::open(repo.path())?;
+        }
+    }
+
+    let shallow_commits = shallow_commits(&repo)?;
+    if !shallow_commits.is_empty() {
+        multiprogress.suspend(|| {
+            eprintln!(
+                "Warning: {} is a shallow clone - its history is cut off at {} commit(s), which are marked shallow rather than is_root",
+                repo_url,
+                shallow_commits.len()
+            )
+        });
+    }
+
+    if settings.recurse_submodules {
+        progress_client.set_message("Updating submodules...");
+        update_submodules(settings, &repo)?;
+        progress_client.set_message("Submodules updated");
+    }
+
+    // Create the revwalk
+    //
+    let mut revwalk = repo.revwalk()?;
+    if settings.chronological {
+        revwalk.set_sorting(Sort::TIME | Sort::REVERSE)?;
+    } else {
+        revwalk.set_sorting(Sort::TIME)?;
+    }
+    // A mirror has every ref locally, not just branches, so walk the lot (its HEAD
+    // isn't necessarily set up to point at anything either)
+    //
+    if settings.mirror {
+        revwalk.push_glob("*")?;
+    }
+    // Up to a given commit instead of a branch's tip
+    //
+    else if let Some(rev) = settings.before_commit.as_deref() {
+        revwalk.push(resolve_revision(
+            &repo,
+            settings,
+            rev,
+            "--before-commit",
+            repo_url,
+        )?)?;
+    }
+    // The remote's default branch rather than the local repo's. Where the local repo
+    // doesn't know which that is, --all-branches doesn't need it
+    //
+    else if let Some(remote) = settings.remote.as_deref() {
+        match repo.refname_to_id(&format!("refs/remotes/{}/HEAD", remote)) {
+            Ok(head) => revwalk.push(head)?,
+            Err(_e) if settings.all_branches => {}
+            Err(_e) => {
+                return Err(Error::Config(format!(
+                    "{} doesn't know the default branch of {} - set it with git remote set-head {} --auto, or use --all-branches",
+                    repo_url, remote, remote
+                )))
+            }
+        }
+    } else {
+        // eprintln!("Adding head");
+        revwalk.push_head()?;
+    }
+
+    // Complete all progress bars
+    //
+    progress_client.finish();
+    progress_server.finish();
+    progress_transfer.finish();
+    progress_indexing.finish();
+
+    // Only the default branch (HEAD) is walked unless asked for all of them
+    //
+    if settings.all_branches {
+        // A fresh clone only has a local branch for the default branch - everything
+        // else is a remote-tracking branch (origin/*). The same tip can be shared by
+        // several branches (origin/HEAD among them) so only push each one once
+        //
+        let mut pushed = HashSet::new();
+        if settings.remote.is_none() {
+            if let Some(head) = repo.head()?.target() {
+                pushed.insert(head);
+            }
+        }
+
+        let branches = repo.branches(Some(BranchType::Remote))?;
+        for (branch, _branch_type) in branches.flatten() {
+            // eprintln!(
+            //     "Adding branch => {} {:?}",
+            //     branch.name()?.unwrap_or("unnamed"),
+            //     branch_type
+            // );
+            if let Some(target) = branch.get().target() {
+                if pushed.insert(target) {
+                    revwalk.push(target)?;
+                }
+            } else {
+                // eprintln!("No valid oid...");
+            }
+        }
+    }
+
+    // Whatever's being walked, the commit and everything before it are left out
+    //
+    if let Some(rev) = settings.after_commit.as_deref() {
+        revwalk.hide(resolve_revision(
+            &repo,
+            settings,
+            rev,
+            "--after-commit",
+            repo_url,
+        )?)?;
+    }
+
+    // What the run being resumed wrote is hidden where it can be, so it's never even
+    // walked. seq counts the commits that were written before, though, so with
+    // --with-sequence they're all walked again (and skipped)
+    //
+    if let Some(emitted) = emitted.filter(|_| !settings.with_sequence) {
+        for oid in fully_emitted(&repo, emitted)? {
+            revwalk.hide(oid)?;
+        }
+    }
+
+    let branch_membership = if settings.with_branches {
+        branch_membership(&repo, settings.mirror)?
+    } else {
+        HashMap::new()
+    };
+
+    let repo_root = settings
+        .with_repo_root
+        .then(|| local_repo_root(repo_url))
+        .flatten();
+
+    // Set on the clone (it's ours to change) so short ids start from this length
+    //
+    if let Some(abbrev) = settings.abbrev {
+        repo.config()?.set_i32("core.abbrev", i32::from(abbrev))?;
+    }
+
+    let diff_cache = settings
+        .cache_dir
+        .as_deref()
+        .map(|dir| DiffCache::new(dir, settings))
+        .transpose()?;
+
+    // Written ahead of the walk, being newer than anything in it. It isn't a commit,
+    // so it's not counted as one
+    //
+    if settings.include_uncommitted {
+        if let Some(mut working) = working_commit(settings, repo_url, repo_root.clone())? {
+            if settings.with_record_hash {
+                working.record_hash = Some(record_hash(&working)?);
+            }
+            sink.write_commit(&working)?;
+        }
+    }
+
+    // Walking the history for its ids alone is quick next to diffing it, and gives
+    // the diff phase's bar its length. --streaming doesn't hold on to the ids (a
+    // little over 20 bytes a commit), taking each one from the walk as it's needed,
+    // so the bar can only count up
+    //
+    let (oids, progress_diff): (Box<dyn Iterator<Item = Result<Oid, git2::Error>>>, _) =
+        if settings.streaming {
+            (Box::new(revwalk), ProgressBar::no_length())
+        } else {
+            let mut oids = revwalk.collect::<Result<Vec<Oid>, git2::Error>>()?;
+            if settings.stable_order {
+                stable_order(&repo, &mut oids, settings.chronological)?;
+            }
+            let progress_diff = ProgressBar::new(oids.len() as u64);
+            (Box::new(oids.into_iter().map(Ok)), progress_diff)
+        };
+    let progress_diff = multiprogress.add(progress_diff);
+    progress_diff.set_style(sty.clone());
+    progress_diff.set_message("Diffing...");
+
+    let mut commit_count = 0;
+    let mut skipped = Vec::new();
+    // seq is the position in the walk - it counts commits skipped by --resume too,
+    // so it's the same whether or not a run was interrupted
+    //
+    for (seq, oid) in (0_u64..).zip(oids) {
+        // Every commit walked moves the bar on, whether or not it's written
+        //
+        progress_diff.inc(1);
+        let oid = oid?;
+
+        // Already written by the run being resumed, and not hidden from the walk
+        //
+        if emitted.is_some_and(|emitted| emitted.contains(&oid.to_string())) {
+            continue;
+        }
+
+        let commit = repo.find_commit(oid)?;
+
+        // Checked before anything is diffed, so filtered out commits cost next to
+        // nothing
+        //
+        // A message in some other encoding (see --with-encoding) is kept as far as
+        // it reads as UTF-8
+        //
+        let message = String::from_utf8_lossy(commit.message_bytes());
+        if let Some(grep) = settings.grep.as_ref() {
+            if grep.is_match(&message) == settings.grep_invert {
+                continue;
+            }
+        }
+        let wanted_type = match settings.type_filter {
+            TypeFilter::All => true,
+            TypeFilter::Normal => commit.parent_count() <= 1,
+            TypeFilter::Merge => commit.parent_count() > 1,
+        };
+        if !wanted_type {
+            continue;
+        }
+        let author = commit.author();
+        let (author_name, author_email) = match (
+            signature_field(author.name(), settings.on_missing_name),
+            signature_field(author.email(), settings.on_missing_email),
+        ) {
+            (Some(name), Some(email)) => (name, email),
+            _ => continue,
+        };
+
+        let commit_tree = repo.find_tree(commit.tree_id())?;
+
+        // println!(
+        //     "Oid => {}, Author => {} {}, Message => {}",
+        //     oid,
+        //     author.name().unwrap_or("unknown"),
+        //     author.email().unwrap_or("unknown"),
+        //     commit.message().unwrap_or("unknown")
+        // );
+
+        // Merges (more than 1 parent) are only diffed when asked, against the parent
+        // picked with --merge-parent
+        //
+        let is_merge = commit.parent_count() > 1;
+        let parent_index = if is_merge {
+            settings.merge_parent
+        } else {
+            Some(0)
+        };
+
+        if let Some(index) = parent_index.filter(|_| is_merge) {
+            if index >= commit.parent_count() {
+                return Err(Error::Config(format!(
+                    "--merge-parent {} is out of range for merge {}, which has {} parents",
+                    index,
+                    oid,
+                    commit.parent_count()
+                )));
+            }
+        }
+
+        let parent_commit = if commit.parent_count() == 0 {
+            // It's a root commit, diffed against the empty tree. There can be more
+            // than one - unrelated histories merged together each bring their own -
+            // and the walk reaches every one of them through the merge's parents
+            //
+            None
+        } else {
+            parent_index
+                .map(|index| commit.parent(index))
+                .transpose()?
+                .map(|parent| parent.tree_id())
+        };
+
+        let parent_tree = parent_commit.map(|oid| repo.find_tree(oid)).transpose()?;
+
+        // git's own abbreviation - at least core.abbrev (or --abbrev) long, and
+        // longer wherever that's needed to be unique in the repo
+        //
+        let id = oid.to_string();
+        let short_id = commit
+            .as_object()
+            .short_id()?
+            .as_str()
+            .unwrap_or(&id)
+            .to_string();
+        progress_diff.set_message(short_id.clone());
+
+        let split_message = settings.split_message.then(|| split_message(&message));
+        let (squash, pr_number) = squash_merge(settings, &message, is_merge);
+
+        let default_commit = Commit {
+            schema_version: SCHEMA_VERSION,
+            record_kind: settings.emit_errors.then_some("commit"),
+            id,
+            short_id,
+            tree_id: Some(commit.tree_id().to_string()),
+            r#type: CommitType::Normal,
+            is_merge,
+            squash,
+            pr_number,
+            parent_count: commit.parent_count() as u32,
+            is_root: commit.parent_count() == 0 && !shallow_commits.contains(&oid),
+            shallow: shallow_commits.contains(&oid),
+            is_working: false,
+            branches: settings
+                .with_branches
+                .then(|| branch_membership.get(&oid).cloned().unwrap_or_default()),
+            record_hash: None,
+            repo_url: label.to_string(),
+            repo_root: repo_root.clone(),
+            seq: settings.with_sequence.then_some(seq),
+            timestamp: match settings.date_source {
+                DateSource::Author => commit.author().when().seconds(),
+                DateSource::Committer => commit.time().seconds(),
+            },
+            author_tz_offset_minutes: commit.author().when().offset_minutes(),
+            committer_tz_offset_minutes: commit.committer().when().offset_minutes(),
+            author_name,
+            author_email,
+            message: message.to_string(),
+            subject: split_message.as_ref().map(|(subject, _)| subject.clone()),
+            body: split_message.map(|(_, body)| body),
+            message_length: settings.split_message.then(|| message.chars().count()),
+            encoding: settings
+                .with_encoding
+                .then(|| commit.message_encoding().map(String::from))
+                .flatten(),
+            changes: Vec::new(),
+        };
+
+        // If it's a merge type (multiple parents), then we ignore the file changes -
+        // unless there's a --merge-parent to diff it against
+        //
+        let mut my_commit = if is_merge && parent_index.is_none() {
+            Commit {
+                r#type: CommitType::Merge,
+                ..default_commit
+            }
+        }
+        // With --no-diff there's nothing more to do than the commit itself
+        //
+        else if settings.no_diff {
+            Commit {
+                r#type: if is_merge {
+                    CommitType::Merge
+                } else {
+                    CommitType::Normal
+                },
+                ..default_commit
+            }
+        }
+        // If it's a normal single (or zero) parent commit, then we process the diff
+        // to the parent to grab file changes
+        //
+        else {
+            let diffed = || -> Result<Vec<FileChange>, Error> {
+                let cached = diff_cache
+                    .as_ref()
+                    .and_then(|cache| cache.get(parent_commit, commit.tree_id()));
+
+                let file_changes = match cached {
+                    Some(file_changes) => file_changes,
+                    None => {
+                        let mut diff = repo.diff_tree_to_tree(
+                            parent_tree.as_ref(),
+                            Some(&commit_tree),
+                            Some(&mut diff_options()),
+                        )?;
+                        find_similar(&mut diff, settings)?;
+
+                        let file_changes = changes_from_diff(
+                            &repo,
+                            &diff,
+                            &Attributes::from_tree(&repo, &commit_tree),
+                            &diff_settings(settings),
+                        )?;
+                        if let Some(cache) = diff_cache.as_ref() {
+                            cache.put(parent_commit, commit.tree_id(), &file_changes)?;
+                        }
+                        file_changes
+                    }
+                };
+                if settings.strict {
+                    check_line_counts(&default_commit.id, &file_changes)?;
+                }
+                Ok(file_changes)
+            };
+
+            // A commit that can't be diffed (a blob missing from the repo, say) only
+            // fails the repo when the run stops at the first failure anyway -
+            // otherwise it's left out and the rest of the repo carried on with
+            //
+            let file_changes = match diffed() {
+                Ok(file_changes) => file_changes,
+                Err(e) if settings.continue_on_error => {
+                    eprintln!("Skipping commit {}: {}", default_commit.id, e);
+                    skipped.push(SkippedCommit {
+                        id: default_commit.id,
+                        error: e,
+                    });
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            Commit {
+                r#type: if is_merge {
+                    CommitType::Merge
+                } else {
+                    CommitType::Normal
+                },
+                changes: tidy_changes(file_changes, settings),
+                ..default_commit
+            }
+        };
+
+        // Last, so it covers the record exactly as it's written
+        //
+        if settings.with_record_hash {
+            my_commit.record_hash = Some(record_hash(&my_commit)?);
+        }
+
+        sink.write_commit(&my_commit)?;
+
+        commit_count += 1;
+    }
+    progress_diff.finish_with_message("Diff complete");
+
+    // libgit2 clones a plain local path by copying its object files, with no
+    // transfer to count - only a file:// URL goes through one
+    //
+    let copied_locally = !repo_url.starts_with("file://") && local_repo_path(repo_url).is_some();
+
+    Ok(RepoStats {
+        commits: commit_count,
+        clone_bytes: (!copied_locally).then(|| clone_bytes.get()),
+        skipped,
+    })
+}
+
+// What was extracted from a repo, and what it cost to clone - clone_bytes is None
+// for a local path, which is copied rather than transferred. Commits left out
+// under --continue-on-error because they couldn't be diffed are in skipped
+//
+pub struct RepoStats {
+    pub commits: usize,
+    pub clone_bytes: Option<usize>,
+    pub skipped: Vec<SkippedCommit>,
+}
+
+pub struct SkippedCommit {
+    pub id: String,
+    pub error: Error,
+}
+
+// How each repo went - what was extracted, or why it failed
+//
+pub type RepoOutcome = (String, Result<RepoStats, Error>);
+
+// Commit ids already in the output, by repo_url
+//
+pub type EmittedCommits = HashMap<String, HashSet<String>>;
+
+// Clones and walks each repo (up to settings.jobs at once), calling on_commit with
+// each commit as it's extracted rather than writing it anywhere. An error from
+// on_commit fails the repo it came from, the same as a write to the output
+// failing. The commits in emitted are skipped - pass an empty map for all of them
+//
+pub fn run_with<F>(
+    settings: &Settings,
+    repo_urls: &[String],
+    emitted: &EmittedCommits,
+    on_commit: F,
+) -> Result<Vec<RepoOutcome>, Error>
+where
+    F: FnMut(&Commit) -> Result<(), Error>,
+{
+    let mut sink = CallbackSink(on_commit);
+    let progress = Progress::new(settings)?;
+
+    // libgit2 keeps the objects it parses (up to 256MiB of them) in case they're
+    // needed again, which the walk and diffs seldom do - the bulk of the memory
+    // used on a long history. It's a setting for the whole process
+    //
+    if settings.streaming {
+        git2::opts::enable_caching(false);
+    }
+
+    if settings.jobs > 1 {
+        return Ok(run_parallel(
+            settings, repo_urls, emitted, &progress, &mut sink,
+        ));
+    }
+
+    // Each repo gets its own clone and walk - keep track of how each one went
+    // so we can report on all of them at the end
+    //
+    let mut outcomes: Vec<RepoOutcome> = Vec::new();
+
+    for repo_url in repo_urls.iter() {
+        let outcome = extract_logs(
+            settings,
+            repo_url,
+            emitted.get(repo_label(settings, repo_url).as_ref()),
+            &progress,
+            &mut sink,
+        );
+        let failed = outcome.is_err();
+
+        if let Err(e) = &outcome {
+            eprintln!("Err {:?}", e);
+        }
+        outcomes.push((repo_url.to_string(), outcome));
+
+        if failed && !settings.continue_on_error {
+            break;
+        }
+    }
+
+    Ok(outcomes)
+}
+
+struct CallbackSink<F>(F);
+
+impl<F: FnMut(&Commit) -> Result<(), Error>> OutputSink for CallbackSink<F> {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        (self.0)(commit)
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// Hands each commit from a worker over to the thread doing the writing
+//
+struct ChannelSink {
+    // Which of the repos given the commits are from
+    //
+    index: usize,
+    commits: mpsc::SyncSender<(usize, Commit)>,
+}
+
+impl OutputSink for ChannelSink {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        self.commits
+            .send((self.index, commit.clone()))
+            .map_err(|e| Error::output("Output stopped", e))
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// --jobs workers each take the next repo, clone and walk it in its own tempdir,
+// and pass the commits back here. Only this thread writes to the sink, so records
+// are never interleaved - though commits from different repos are, in whatever
+// order they're finished. Outcomes are reported in the order the repos were given,
+// with a failed write reported as the outcome of the repo its commit came from
+//
+fn run_parallel(
+    settings: &Settings,
+    repo_urls: &[String],
+    emitted: &EmittedCommits,
+    progress: &Progress,
+    sink: &mut dyn OutputSink,
+) -> Vec<RepoOutcome> {
+    let next_repo = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let outcomes: Mutex<Vec<(usize, RepoOutcome)>> = Mutex::new(Vec::new());
+    let jobs = usize::from(settings.jobs);
+    let (commits, received) = mpsc::sync_channel::<(usize, Commit)>(jobs * 16);
+    let mut write_errors: HashMap<usize, Error> = HashMap::new();
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(repo_urls.len()) {
+            let mut worker_sink = ChannelSink {
+                index: 0,
+                commits: commits.clone(),
+            };
+            let (next_repo, stop, outcomes) = (&next_repo, &stop, &outcomes);
+
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next_repo.fetch_add(1, Ordering::Relaxed);
+                    let repo_url = match repo_urls.get(index) {
+                        Some(repo_url) => repo_url,
+                        None => break,
+                    };
+                    worker_sink.index = index;
+
+                    let outcome = extract_logs(
+                        settings,
+                        repo_url,
+                        emitted.get(repo_label(settings, repo_url).as_ref()),
+                        progress,
+                        &mut worker_sink,
+                    );
+                    if let Err(e) = &outcome {
+                        eprintln!("Err {:?}", e);
+                        if !settings.continue_on_error {
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    if let Ok(mut outcomes) = outcomes.lock() {
+                        outcomes.push((index, (repo_url.to_string(), outcome)));
+                    }
+                }
+            });
+        }
+        drop(commits);
+
+        // Runs until every worker has finished (and dropped its sender). If writing
+        // fails the receiver is dropped, which fails the workers' next send - unless
+        // continuing on errors, when only the rest of that repo's commits are dropped
+        //
+        for (index, commit) in received {
+            if write_errors.contains_key(&index) {
+                continue;
+            }
+            if let Err(e) = sink.write_commit(&commit) {
+                eprintln!("Err {:?}", e);
+                write_errors.insert(index, e);
+                if !settings.continue_on_error {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut outcomes = outcomes.into_inner().unwrap_or_default();
+    outcomes.sort_by_key(|(index, _)| *index);
+    outcomes
+        .into_iter()
+        .map(
+            |(index, (repo_url, outcome))| match write_errors.remove(&index) {
+                Some(e) => (repo_url, Err(e)),
+                None => (repo_url, outcome),
+            },
+        )
+        .collect()
+}
+
+// The time now, in seconds since the epoch like every other timestamp written
+//
+pub fn unix_time() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::ChangeStatus;
+
+    fn change(
+        path: &str,
+        status: ChangeStatus,
+        lines_added: u32,
+        lines_removed: u32,
+    ) -> FileChange {
+        FileChange {
+            path: path.to_string(),
+            status,
+            lines_added,
+            lines_removed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn a_delete_and_add_of_one_path_merge_into_a_typechange() {
+        let merged = merge_duplicate_paths(vec![
+            change("link", ChangeStatus::Deleted, 0, 3),
+            change("other.txt", ChangeStatus::Modified, 1, 1),
+            change("link", ChangeStatus::Added, 1, 0),
+        ]);
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].path, "link");
+        assert_eq!(merged[0].status, ChangeStatus::Typechange);
+        assert_eq!((merged[0].lines_added, merged[0].lines_removed), (1, 3));
+        assert_eq!(merged[1].path, "other.txt");
+    }
+
+    // A repo with a line of commits, oldest first, each with an empty tree
+    //
+    fn linear_history(length: usize) -> (tempfile::TempDir, Repository, Vec<Oid>) {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::now("Ada", "ada@example.com").unwrap();
+        let tree_id = repo.treebuilder(None).unwrap().write().unwrap();
+
+        let mut oids: Vec<Oid> = Vec::new();
+        {
+            let tree = repo.find_tree(tree_id).unwrap();
+            for n in 0..length {
+                let parent = oids.last().map(|oid| repo.find_commit(*oid).unwrap());
+                let parents: Vec<&git2::Commit> = parent.iter().collect();
+                let message = format!("Commit {}", n);
+                let oid = repo
+                    .commit(None, &signature, &signature, &message, &tree, &parents)
+                    .unwrap();
+                oids.push(oid);
+            }
+        }
+        (dir, repo, oids)
+    }
+
+    #[test]
+    fn only_commits_written_along_with_their_history_are_fully_emitted() {
+        let (_dir, repo, oids) = linear_history(4);
+        let ids = |indexes: &[usize]| -> HashSet<String> {
+            indexes.iter().map(|&i| oids[i].to_string()).collect()
+        };
+
+        // Oldest first, as a --chronological run writes them
+        //
+        let complete = fully_emitted(&repo, &ids(&[0, 1])).unwrap();
+        assert_eq!(complete, HashSet::from([oids[0], oids[1]]));
+
+        // Newest first - their history is still to come
+        //
+        assert!(fully_emitted(&repo, &ids(&[2, 3])).unwrap().is_empty());
+
+        // A gap stops everything after it
+        //
+        let complete = fully_emitted(&repo, &ids(&[0, 2, 3])).unwrap();
+        assert_eq!(complete, HashSet::from([oids[0]]));
+
+        let mut with_working = ids(&[0]);
+        with_working.insert(WORKING_ID.to_string());
+        assert_eq!(
+            fully_emitted(&repo, &with_working).unwrap(),
+            HashSet::from([oids[0]])
+        );
+    }
+
+    #[test]
+    fn clones_running_faster_than_the_cap_are_held_back() {
+        let second = Duration::from_secs(1);
+        // 1000 bytes at 100 a second should take 10 seconds
+        //
+        assert_eq!(
+            throttle_delay(1000, second, 100),
+            Some(Duration::from_secs(9))
+        );
+        assert_eq!(
+            throttle_delay(150, second, 100),
+            Some(Duration::from_millis(500))
+        );
+        // At or under the cap
+        //
+        assert_eq!(throttle_delay(100, second, 100), None);
+        assert_eq!(throttle_delay(50, second, 100), None);
+        assert_eq!(throttle_delay(0, Duration::ZERO, 100), None);
+        // No cap
+        //
+        assert_eq!(throttle_delay(1000, second, 0), None);
+    }
+
+    #[test]
+    fn messages_split_into_a_subject_and_body_at_the_first_blank_line() {
+        assert_eq!(
+            split_message("Just a subject\n"),
+            ("Just a subject".to_string(), String::new())
+        );
+        assert_eq!(
+            split_message("Subject\n\nFirst paragraph\nstill first\n\nSecond paragraph\n"),
+            (
+                "Subject".to_string(),
+                "First paragraph\nstill first\n\nSecond paragraph".to_string()
+            )
+        );
+        // A subject running over more than one line is joined back up, as git does
+        //
+        assert_eq!(
+            split_message("\nA long\nsubject\n\n\nBody\n"),
+            ("A long subject".to_string(), "Body".to_string())
+        );
+        assert_eq!(split_message(""), (String::new(), String::new()));
+    }
+
+    #[test]
+    fn os_paths_are_written_slash_separated() {
+        assert_eq!(
+            slash_separated("C:\\repos/mixed\\one", '\\'),
+            "C:/repos/mixed/one"
+        );
+        // Where / is the separator a \ is just part of a name
+        //
+        assert_eq!(
+            slash_separated("/repos/mixed\\one", '/'),
+            "/repos/mixed\\one"
+        );
+    }
+
+    #[test]
+    fn hunks_without_any_lines_fail_the_strict_check() {
+        let mut counted = change("counted.txt", ChangeStatus::Modified, 2, 1);
+        counted.hunks_modified = 1;
+        let renamed = change("renamed.txt", ChangeStatus::Renamed, 0, 0);
+        assert!(check_line_counts("abc123", &[counted.clone(), renamed]).is_ok());
+
+        // A hunk the callbacks lost every line of
+        //
+        let mut broken = change("broken.txt", ChangeStatus::Modified, 0, 0);
+        broken.hunks_added = 2;
+        match check_line_counts("abc123", &[counted, broken]) {
+            Err(Error::Invariant(message)) => assert_eq!(
+                message,
+                "Commit abc123 file broken.txt has 2 hunk(s) but no lines added, removed or modified"
+            ),
+            other => panic!("expected an Invariant error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn extensions_match_whatever_their_case() {
+        let rust = ["rs".to_string()];
+        assert!(wanted_extension("src/main.rs", &rust));
+        assert!(wanted_extension("SRC/MAIN.RS", &rust));
+        assert!(!wanted_extension("Cargo.toml", &rust));
+        assert!(!wanted_extension("src/rs", &rust));
+        assert!(!wanted_extension("Makefile", &rust));
+
+        // An empty entry is for files with no extension, dotfiles included
+        //
+        let with_none = ["RS".to_string(), String::new()];
+        assert!(wanted_extension("src/lib.rs", &with_none));
+        assert!(wanted_extension("Makefile", &with_none));
+        assert!(wanted_extension(".gitignore", &with_none));
+        assert!(!wanted_extension("README.md", &with_none));
+        assert!(wanted_extension("notes.md", &[".md".to_string()]));
+    }
+
+    #[test]
+    fn squash_merges_are_spotted_by_the_pr_number_in_their_subject() {
+        let settings = Settings {
+            detect_squash: true,
+            ..Settings::default()
+        };
+        let squashed = "Fix the parser (#123)\n\n* Handle empty input\n\n* Add a test\n";
+        assert_eq!(
+            squash_merge(&settings, squashed, false),
+            (Some(true), Some(123))
+        );
+        // Only at the end of the subject, and merges are merges
+        //
+        assert_eq!(
+            squash_merge(&settings, "Fix (#123) the parser\n", false),
+            (Some(false), None)
+        );
+        assert_eq!(
+            squash_merge(&settings, "Fix the parser\n\nSee (#123)\n", false),
+            (Some(false), None)
+        );
+        assert_eq!(squash_merge(&settings, squashed, true), (Some(false), None));
+
+        let custom = Settings {
+            squash_pattern: regex::Regex::new(r"\[PR-(\d+)\]$").unwrap(),
+            ..settings
+        };
+        assert_eq!(
+            squash_merge(&custom, "Fix the parser [PR-45]\n", false),
+            (Some(true), Some(45))
+        );
+        assert_eq!(squash_merge(&custom, squashed, false), (Some(false), None));
+
+        assert_eq!(
+            squash_merge(&Settings::default(), squashed, false),
+            (None, None)
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{commit::Commit, error::Error, sink::OutputSink};
+use rdkafka::{
+    config::ClientConfig,
+    error::{KafkaError, RDKafkaErrorCode},
+    producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext},
+    ClientContext,
+};
+use std::{sync::Mutex, time::Duration};
+
+// How long finish waits for the messages still in flight to be acknowledged
+//
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Keeps the first delivery failure reported by the broker. Deliveries are only
+// confirmed some time after a message is sent, so this is checked as we go and
+// again once everything has been flushed
+//
+#[derive(Default)]
+struct DeliveryContext {
+    failure: Mutex<Option<KafkaError>>,
+}
+
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _opaque: ()) {
+        if let Err((e, _message)) = result {
+            if let Ok(mut failure) = self.failure.lock() {
+                failure.get_or_insert_with(|| e.clone());
+            }
+        }
+    }
+}
+
+// Produces one message per row (or per commit when nested) to a Kafka topic, keyed
+// by the commit id so all of a commit's rows land on the same partition
+//
+pub struct KafkaSink {
+    producer: BaseProducer<DeliveryContext>,
+    topic: String,
+    nested: bool,
+}
+
+impl KafkaSink {
+    pub fn new(brokers: &str, topic: &str, acks: &str, nested: bool) -> Result<KafkaSink, Error> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("acks", acks)
+            .create_with_context(DeliveryContext::default())
+            .map_err(|e| Error::output("Kafka failed", e))?;
+
+        Ok(KafkaSink {
+            producer,
+            topic: topic.to_string(),
+            nested,
+        })
+    }
+
+    fn send<T: serde::Serialize>(&self, id: &str, record: &T) -> Result<(), Error> {
+        let payload = serde_json::to_vec(record)
+            .map_err(|e| Error::serde(format!("Serde failed for commit {}", id), e))?;
+        let mut message = BaseRecord::to(&self.topic).key(id).payload(&payload);
+
+        // The producer's queue fills up when the broker can't keep up - wait for
+        // some of it to be delivered and try again
+        //
+        loop {
+            match self.producer.send(message) {
+                Ok(()) => return Ok(()),
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), unsent)) => {
+                    message = unsent;
+                    self.producer.poll(Duration::from_millis(100));
+                }
+                Err((e, _unsent)) => return Err(Error::output("Kafka failed", e)),
+            }
+        }
+    }
+
+    fn check_deliveries(&self) -> Result<(), Error> {
+        match self.producer.context().failure.lock() {
+            Ok(mut failure) => match failure.take() {
+                Some(e) => Err(Error::output("Kafka delivery failed", e)),
+                None => Ok(()),
+            },
+            Err(_e) => Ok(()),
+        }
+    }
+}
+
+impl OutputSink for KafkaSink {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        if self.nested {
+            self.send(&commit.id, commit)?;
+        } else {
+            for flat in commit.flat() {
+                self.send(&commit.id, &flat)?;
+            }
+        }
+
+        self.producer.poll(Duration::ZERO);
+        self.check_deliveries()
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        self.producer
+            .flush(FLUSH_TIMEOUT)
+            .map_err(|e| Error::output("Kafka flush failed", e))?;
+        self.check_deliveries()
+    }
+}
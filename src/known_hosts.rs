@@ -0,0 +1,257 @@
+use crate::error::Error;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::fs;
+
+// What an OpenSSH known_hosts file says about a host key
+//
+#[derive(Debug, PartialEq)]
+pub enum HostKeyStatus {
+    // The key is listed for the host
+    Known,
+    // The host is listed, but not with this key
+    Mismatch,
+    // The key is marked @revoked
+    Revoked,
+    // The host isn't listed at all
+    Unknown,
+}
+
+// The entries of a known_hosts file - markers, host patterns (plain, wildcarded,
+// negated or hashed) and the base64 decoded key blob
+//
+pub struct KnownHosts {
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    revoked: bool,
+    hosts: String,
+    key: Vec<u8>,
+}
+
+impl KnownHosts {
+    pub fn load(path: &str) -> Result<KnownHosts, Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::io(format!("Unable to read known_hosts {}", path), e))?;
+        Ok(KnownHosts::parse(&contents))
+    }
+
+    pub fn parse(contents: &str) -> KnownHosts {
+        let entries = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace().peekable();
+
+                // CA keys sign host certificates, which libgit2 never hands over
+                //
+                let revoked = match fields.peek() {
+                    Some(&"@revoked") => {
+                        fields.next();
+                        true
+                    }
+                    Some(&"@cert-authority") => return None,
+                    _ => false,
+                };
+
+                let hosts = fields.next()?.to_string();
+                let _key_type = fields.next()?;
+                let key = STANDARD.decode(fields.next()?).ok()?;
+                Some(Entry {
+                    revoked,
+                    hosts,
+                    key,
+                })
+            })
+            .collect();
+
+        KnownHosts { entries }
+    }
+
+    // host is as written in known_hosts - the host name, or [host]:port for anything
+    // not on port 22
+    //
+    pub fn check(&self, host: &str, key: &[u8]) -> HostKeyStatus {
+        let mut status = HostKeyStatus::Unknown;
+
+        for entry in self.entries.iter().filter(|e| hosts_match(&e.hosts, host)) {
+            if entry.key == key {
+                if entry.revoked {
+                    return HostKeyStatus::Revoked;
+                }
+                status = HostKeyStatus::Known;
+            } else if !entry.revoked && status == HostKeyStatus::Unknown {
+                status = HostKeyStatus::Mismatch;
+            }
+        }
+        status
+    }
+}
+
+fn hosts_match(patterns: &str, host: &str) -> bool {
+    if let Some(hashed) = patterns.strip_prefix("|1|") {
+        return hashed_host_matches(hashed, host);
+    }
+
+    let mut matched = false;
+    for pattern in patterns.split(',') {
+        match pattern.strip_prefix('!') {
+            Some(negated) if glob_match(negated, host) => return false,
+            Some(_) => {}
+            None => matched |= glob_match(pattern, host),
+        }
+    }
+    matched
+}
+
+// HashKnownHosts entries are |1|<salt>|<hmac-sha1 of the host keyed with the salt>
+//
+fn hashed_host_matches(hashed: &str, host: &str) -> bool {
+    let (salt, hash) = match hashed.split_once('|') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let (salt, hash) = match (STANDARD.decode(salt), STANDARD.decode(hash)) {
+        (Ok(salt), Ok(hash)) => (salt, hash),
+        _ => return false,
+    };
+
+    match Hmac::<Sha1>::new_from_slice(&salt) {
+        Ok(mut mac) => {
+            mac.update(host.as_bytes());
+            mac.verify_slice(&hash).is_ok()
+        }
+        Err(_e) => false,
+    }
+}
+
+// known_hosts patterns only have * and ? wildcards
+//
+fn glob_match(pattern: &str, host: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let host: Vec<char> = host.to_lowercase().chars().collect();
+
+    let (mut p, mut h) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while h < host.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, h));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == host[h] => {
+                p += 1;
+                h += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    h = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"the server's key";
+    const OTHER_KEY: &[u8] = b"someone else's key";
+
+    fn known_hosts(lines: &[String]) -> KnownHosts {
+        KnownHosts::parse(&lines.join("\n"))
+    }
+
+    fn entry(hosts: &str, key: &[u8]) -> String {
+        format!("{} ssh-ed25519 {}", hosts, STANDARD.encode(key))
+    }
+
+    #[test]
+    fn listed_keys_are_known_and_others_mismatched() {
+        let known_hosts = known_hosts(&[
+            "# a comment".to_string(),
+            entry("github.com,gitlab.com", KEY),
+        ]);
+
+        assert_eq!(known_hosts.check("gitlab.com", KEY), HostKeyStatus::Known);
+        assert_eq!(
+            known_hosts.check("github.com", OTHER_KEY),
+            HostKeyStatus::Mismatch
+        );
+        assert_eq!(
+            known_hosts.check("example.com", KEY),
+            HostKeyStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn known_hosts_are_loaded_from_a_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), entry("github.com", KEY) + "\n").unwrap();
+
+        let known_hosts = KnownHosts::load(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(known_hosts.check("github.com", KEY), HostKeyStatus::Known);
+        assert!(KnownHosts::load("/nonexistent/known_hosts").is_err());
+    }
+
+    #[test]
+    fn revoked_keys_are_never_trusted() {
+        let known_hosts = known_hosts(&[
+            entry("*", KEY),
+            format!("@revoked {}", entry("*.example.com", KEY)),
+        ]);
+
+        assert_eq!(
+            known_hosts.check("git.example.com", KEY),
+            HostKeyStatus::Revoked
+        );
+        assert_eq!(known_hosts.check("github.com", KEY), HostKeyStatus::Known);
+    }
+
+    #[test]
+    fn wildcards_and_negations_match_like_openssh() {
+        let known_hosts = known_hosts(&[entry("*.example.com,!bad.example.com,git?.org", KEY)]);
+
+        assert_eq!(
+            known_hosts.check("a.example.com", KEY),
+            HostKeyStatus::Known
+        );
+        assert_eq!(
+            known_hosts.check("bad.example.com", KEY),
+            HostKeyStatus::Unknown
+        );
+        assert_eq!(known_hosts.check("git1.org", KEY), HostKeyStatus::Known);
+        assert_eq!(known_hosts.check("git12.org", KEY), HostKeyStatus::Unknown);
+    }
+
+    #[test]
+    fn hashed_hosts_match_with_their_port() {
+        let salt = b"twenty bytes of salt";
+        let mut mac = Hmac::<Sha1>::new_from_slice(salt).unwrap();
+        mac.update(b"[git.example.com]:2222");
+        let hashed = format!(
+            "|1|{}|{}",
+            STANDARD.encode(salt),
+            STANDARD.encode(mac.finalize().into_bytes())
+        );
+        let known_hosts = known_hosts(&[entry(&hashed, KEY)]);
+
+        assert_eq!(
+            known_hosts.check("[git.example.com]:2222", KEY),
+            HostKeyStatus::Known
+        );
+        assert_eq!(
+            known_hosts.check("git.example.com", KEY),
+            HostKeyStatus::Unknown
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::path::Path;
+
+// Files known by their whole name rather than an extension
+//
+const FILE_NAMES: &[(&str, &str)] = &[
+    ("BUILD", "Starlark"),
+    ("BUILD.bazel", "Starlark"),
+    ("CMakeLists.txt", "CMake"),
+    ("Dockerfile", "Dockerfile"),
+    ("Gemfile", "Ruby"),
+    ("GNUmakefile", "Makefile"),
+    ("Jenkinsfile", "Groovy"),
+    ("Makefile", "Makefile"),
+    ("Rakefile", "Ruby"),
+    ("WORKSPACE", "Starlark"),
+    ("makefile", "Makefile"),
+];
+
+// Extensions, lowercased
+//
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("bash", "Shell"),
+    ("bat", "Batchfile"),
+    ("c", "C"),
+    ("cc", "C++"),
+    ("cjs", "JavaScript"),
+    ("clj", "Clojure"),
+    ("cmake", "CMake"),
+    ("cpp", "C++"),
+    ("cs", "C#"),
+    ("css", "CSS"),
+    ("cxx", "C++"),
+    ("dart", "Dart"),
+    ("ex", "Elixir"),
+    ("exs", "Elixir"),
+    ("erl", "Erlang"),
+    ("fs", "F#"),
+    ("go", "Go"),
+    ("gradle", "Groovy"),
+    ("groovy", "Groovy"),
+    ("h", "C"),
+    ("hh", "C++"),
+    ("hpp", "C++"),
+    ("hs", "Haskell"),
+    ("htm", "HTML"),
+    ("html", "HTML"),
+    ("ini", "INI"),
+    ("java", "Java"),
+    ("js", "JavaScript"),
+    ("json", "JSON"),
+    ("jsx", "JavaScript"),
+    ("kt", "Kotlin"),
+    ("kts", "Kotlin"),
+    ("less", "Less"),
+    ("lua", "Lua"),
+    ("m", "Objective-C"),
+    ("md", "Markdown"),
+    ("mjs", "JavaScript"),
+    ("mk", "Makefile"),
+    ("ml", "OCaml"),
+    ("mm", "Objective-C++"),
+    ("php", "PHP"),
+    ("pl", "Perl"),
+    ("proto", "Protocol Buffer"),
+    ("ps1", "PowerShell"),
+    ("py", "Python"),
+    ("r", "R"),
+    ("rb", "Ruby"),
+    ("rs", "Rust"),
+    ("rst", "reStructuredText"),
+    ("sass", "Sass"),
+    ("scala", "Scala"),
+    ("scss", "SCSS"),
+    ("sh", "Shell"),
+    ("sql", "SQL"),
+    ("swift", "Swift"),
+    ("tf", "HCL"),
+    ("toml", "TOML"),
+    ("ts", "TypeScript"),
+    ("tsx", "TypeScript"),
+    ("vue", "Vue"),
+    ("xml", "XML"),
+    ("yaml", "YAML"),
+    ("yml", "YAML"),
+    ("zig", "Zig"),
+    ("zsh", "Shell"),
+];
+
+// The language of a file going by its name alone - None when it isn't one we know
+//
+pub fn detect(path: &str) -> Option<&'static str> {
+    let path = Path::new(path);
+    let file_name = path.file_name()?.to_str()?;
+
+    if let Some((_, language)) = FILE_NAMES.iter().find(|(name, _)| *name == file_name) {
+        return Some(language);
+    }
+
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, language)| *language)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_go_by_extension_or_whole_file_name() {
+        assert_eq!(detect("src/main.rs"), Some("Rust"));
+        assert_eq!(detect("scripts/build.py"), Some("Python"));
+        assert_eq!(detect("README.MD"), Some("Markdown"));
+        assert_eq!(detect("Makefile"), Some("Makefile"));
+        assert_eq!(detect("tools/Makefile"), Some("Makefile"));
+        assert_eq!(detect("CMakeLists.txt"), Some("CMake"));
+    }
+
+    #[test]
+    fn unknown_files_have_no_language() {
+        assert_eq!(detect("data.xyz"), None);
+        assert_eq!(detect("LICENSE"), None);
+        assert_eq!(detect(".gitignore"), None);
+        assert_eq!(detect("notes.txt"), None);
+    }
+}
@@ -0,0 +1,29 @@
+// Everything behind git-fetch-commits, for use without the command line - run_with
+// to clone and walk repos with a callback for each commit, or changes_from_diff for
+// the counting on a git2::Diff of your own
+//
+pub mod attributes;
+mod auth;
+pub mod avro;
+mod cache;
+pub mod commit;
+pub mod diff;
+#[cfg(feature = "elasticsearch")]
+pub mod elasticsearch;
+pub mod error;
+pub mod extract;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+mod known_hosts;
+mod language;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod progress;
+pub mod report;
+pub mod settings;
+pub mod sink;
+
+// Emitted as schema_version in every record so consumers can tell which set of
+// fields to expect. Bump this whenever a field is added, removed or changes meaning
+//
+pub const SCHEMA_VERSION: u32 = 27;
@@ -0,0 +1,244 @@
+use crate::{commit::Commit, error::Error, sink::OutputSink};
+use postgres::{Client, NoTls};
+use serde_json::Value;
+use std::{collections::HashSet, io::Write};
+
+// Commits are buffered and written this many at a time - each batch is one COPY
+// per table and a single transaction
+//
+const BATCH_COMMITS: usize = 500;
+
+const COMMIT_COLUMNS: &[(&str, &str)] = &[
+    ("id", "TEXT PRIMARY KEY"),
+    ("schema_version", "INTEGER"),
+    ("record_kind", "TEXT"),
+    ("short_id", "TEXT"),
+    ("tree_id", "TEXT"),
+    ("repo_url", "TEXT"),
+    ("repo_root", "TEXT"),
+    ("seq", "BIGINT"),
+    ("timestamp", "BIGINT"),
+    ("author_tz_offset_minutes", "INTEGER"),
+    ("committer_tz_offset_minutes", "INTEGER"),
+    ("author_name", "TEXT"),
+    ("author_email", "TEXT"),
+    ("message", "TEXT"),
+    ("subject", "TEXT"),
+    ("body", "TEXT"),
+    ("message_length", "BIGINT"),
+    ("encoding", "TEXT"),
+    ("type", "TEXT"),
+    ("is_merge", "BOOLEAN"),
+    ("squash", "BOOLEAN"),
+    ("pr_number", "BIGINT"),
+    ("parent_count", "INTEGER"),
+    ("is_root", "BOOLEAN"),
+    ("shallow", "BOOLEAN"),
+    ("is_working", "BOOLEAN"),
+    ("record_hash", "TEXT"),
+];
+
+const FILE_CHANGE_COLUMNS: &[(&str, &str)] = &[
+    ("commit_id", "TEXT NOT NULL REFERENCES commits (id)"),
+    ("path", "TEXT"),
+    ("old_path", "TEXT"),
+    ("status", "TEXT"),
+    ("similarity", "INTEGER"),
+    ("language", "TEXT"),
+    ("lines_added", "BIGINT"),
+    ("lines_removed", "BIGINT"),
+    ("lines_modified", "BIGINT"),
+    ("hunks_added", "BIGINT"),
+    ("hunks_removed", "BIGINT"),
+    ("hunks_modified", "BIGINT"),
+    ("additions_in_modified_hunks", "BIGINT"),
+    ("deletions_in_modified_hunks", "BIGINT"),
+    ("words_added", "BIGINT"),
+    ("words_removed", "BIGINT"),
+    ("binary", "BOOLEAN"),
+    ("whitespace_only", "BOOLEAN"),
+    ("submodule_old_id", "TEXT"),
+    ("submodule_new_id", "TEXT"),
+    ("lfs", "BOOLEAN"),
+    ("lfs_size", "BIGINT"),
+    ("old_size", "BIGINT"),
+    ("new_size", "BIGINT"),
+    ("hunks", "JSONB"),
+];
+
+// Writes commits and their file changes to a commits and a file_changes table.
+// Each batch is COPYed into temporary staging tables and moved across from there,
+// as COPY itself can't skip rows that are already present - so commits already in
+// the database (from an earlier run) are left alone, along with their changes
+//
+pub struct PostgresSink {
+    client: Client,
+    commits: Vec<u8>,
+    file_changes: Vec<u8>,
+    batch_ids: HashSet<String>,
+}
+
+impl PostgresSink {
+    pub fn new(conn: &str, create_tables: bool) -> Result<PostgresSink, Error> {
+        let mut client = Client::connect(conn, NoTls).map_err(postgres_error)?;
+
+        if create_tables {
+            client
+                .batch_execute(&format!(
+                    "CREATE TABLE IF NOT EXISTS commits ({});
+                     CREATE TABLE IF NOT EXISTS file_changes ({});",
+                    column_definitions(COMMIT_COLUMNS),
+                    column_definitions(FILE_CHANGE_COLUMNS),
+                ))
+                .map_err(postgres_error)?;
+        }
+
+        client
+            .batch_execute(
+                "CREATE TEMP TABLE staged_commits (LIKE commits) ON COMMIT DELETE ROWS;
+                 CREATE TEMP TABLE staged_file_changes (LIKE file_changes) ON COMMIT DELETE ROWS;",
+            )
+            .map_err(postgres_error)?;
+
+        Ok(PostgresSink {
+            client,
+            commits: Vec::new(),
+            file_changes: Vec::new(),
+            batch_ids: HashSet::new(),
+        })
+    }
+
+    fn write_batch(&mut self) -> Result<(), Error> {
+        if self.batch_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut transaction = self.client.transaction().map_err(postgres_error)?;
+        for (table, columns, rows) in [
+            ("staged_commits", COMMIT_COLUMNS, &self.commits),
+            (
+                "staged_file_changes",
+                FILE_CHANGE_COLUMNS,
+                &self.file_changes,
+            ),
+        ] {
+            let mut writer = transaction
+                .copy_in(&format!(
+                    "COPY {} ({}) FROM STDIN (FORMAT csv)",
+                    table,
+                    column_names(columns)
+                ))
+                .map_err(postgres_error)?;
+            writer
+                .write_all(rows)
+                .map_err(|e| Error::io("Postgres COPY failed", e))?;
+            writer.finish().map_err(postgres_error)?;
+        }
+
+        transaction
+            .batch_execute(&format!(
+                "WITH inserted AS (
+                     INSERT INTO commits ({commits}) SELECT {commits} FROM staged_commits
+                     ON CONFLICT (id) DO NOTHING
+                     RETURNING id
+                 )
+                 INSERT INTO file_changes ({file_changes})
+                 SELECT {staged_file_changes} FROM staged_file_changes s
+                 JOIN inserted ON inserted.id = s.commit_id",
+                commits = column_names(COMMIT_COLUMNS),
+                file_changes = column_names(FILE_CHANGE_COLUMNS),
+                staged_file_changes = FILE_CHANGE_COLUMNS
+                    .iter()
+                    .map(|(name, _)| format!("s.\"{}\"", name))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ))
+            .map_err(postgres_error)?;
+        transaction.commit().map_err(postgres_error)?;
+
+        self.commits.clear();
+        self.file_changes.clear();
+        self.batch_ids.clear();
+        Ok(())
+    }
+}
+
+impl OutputSink for PostgresSink {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        // The same commit can come from more than one repo (a fork, say) - only the
+        // first is kept, as it would be across batches
+        //
+        if !self.batch_ids.insert(commit.id.clone()) {
+            return Ok(());
+        }
+
+        let record = serde_json::to_value(commit)
+            .map_err(|e| Error::serde(format!("Serde failed for commit {}", commit.id), e))?;
+        write_csv_row(&mut self.commits, COMMIT_COLUMNS, |name| &record[name]);
+
+        for change in record["changes"].as_array().into_iter().flatten() {
+            write_csv_row(
+                &mut self.file_changes,
+                FILE_CHANGE_COLUMNS,
+                |name| match name {
+                    "commit_id" => &record["id"],
+                    _ => &change[name],
+                },
+            );
+        }
+
+        if self.batch_ids.len() >= BATCH_COMMITS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        self.write_batch()
+    }
+}
+
+fn column_definitions(columns: &[(&str, &str)]) -> String {
+    columns
+        .iter()
+        .map(|(name, sql_type)| format!("\"{}\" {}", name, sql_type))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn column_names(columns: &[(&str, &str)]) -> String {
+    columns
+        .iter()
+        .map(|(name, _)| format!("\"{}\"", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// In COPY's csv format an unquoted empty field is NULL, so every value is quoted
+// to keep empty strings apart from nulls. Nested values (the hunk list) are kept
+// as their JSON text
+//
+fn write_csv_row<'a>(
+    out: &mut Vec<u8>,
+    columns: &[(&str, &str)],
+    value: impl Fn(&str) -> &'a Value,
+) {
+    for (i, (name, _)) in columns.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        let text = match value(name) {
+            Value::Null => continue,
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        out.push(b'"');
+        out.extend_from_slice(text.replace('"', "\"\"").as_bytes());
+        out.push(b'"');
+    }
+    out.push(b'\n');
+}
+
+fn postgres_error(e: postgres::Error) -> Error {
+    Error::output("Postgres failed", e)
+}
@@ -0,0 +1,153 @@
+use crate::{
+    error::Error,
+    settings::{ColorChoice, ProgressChars, ProgressFormat, Settings},
+};
+use indicatif::{style::TemplateError, MultiProgress, ProgressDrawTarget, ProgressStyle, TermLike};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+};
+
+// Progress written to a file rather than a terminal. There's no cursor to move, so
+// every redraw is simply appended - the file reads as a log of the bars over time
+//
+#[derive(Debug)]
+pub struct ProgressFile {
+    file: Mutex<File>,
+}
+
+impl ProgressFile {
+    // Appends, as the file is reopened for each repo
+    //
+    pub fn open(path: &str) -> io::Result<ProgressFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(ProgressFile {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(&self, s: &str) -> io::Result<()> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_e| io::Error::other("Progress file lock poisoned"))?;
+        file.write_all(s.as_bytes())
+    }
+}
+
+impl TermLike for ProgressFile {
+    fn width(&self) -> u16 {
+        80
+    }
+
+    fn move_cursor_up(&self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_down(&self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_right(&self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_left(&self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&self, s: &str) -> io::Result<()> {
+        self.write(&format!("{}\n", s))
+    }
+
+    // indicatif returns to the start of the line with \r before each redraw and pads
+    // out the last line of the bars with spaces - in a file that padding becomes the
+    // end of the line instead
+    //
+    fn write_str(&self, s: &str) -> io::Result<()> {
+        match s {
+            "\r" => Ok(()),
+            s if s.trim().is_empty() => self.write("\n"),
+            s => self.write(s),
+        }
+    }
+
+    fn clear_line(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_e| io::Error::other("Progress file lock poisoned"))?;
+        file.flush()
+    }
+}
+
+// Where progress goes for the whole run, shared by every repo (and worker).
+// With JSON progress the bars are still kept up to date, just never drawn. The
+// events (or the bars) go to --progress-file instead of stderr when it's given
+//
+pub struct Progress {
+    pub multiprogress: MultiProgress,
+    pub json_file: Option<ProgressFile>,
+}
+
+impl Progress {
+    pub fn new(settings: &Settings) -> Result<Progress, Error> {
+        let progress_file = settings
+            .progress_file
+            .as_deref()
+            .map(ProgressFile::open)
+            .transpose()
+            .map_err(|e| Error::io("Progress file failed", e))?;
+
+        Ok(match (settings.progress_format, progress_file) {
+            (ProgressFormat::Json, json_file) => Progress {
+                multiprogress: MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+                json_file,
+            },
+            (ProgressFormat::Bars, Some(progress_file)) => Progress {
+                multiprogress: MultiProgress::with_draw_target(
+                    ProgressDrawTarget::term_like_with_hz(Box::new(progress_file), 1),
+                ),
+                json_file: None,
+            },
+            (ProgressFormat::Bars, None) => Progress {
+                multiprogress: MultiProgress::new(),
+                json_file: None,
+            },
+        })
+    }
+}
+
+const DEFAULT_PROGRESS_TEMPLATE: &str =
+    "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}";
+
+pub fn colors_enabled(settings: &Settings) -> bool {
+    match settings.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && settings.progress_file.is_none()
+                && io::stderr().is_terminal()
+        }
+    }
+}
+
+pub fn progress_style(settings: &Settings) -> Result<ProgressStyle, TemplateError> {
+    let template = settings
+        .progress_template
+        .as_deref()
+        .unwrap_or(DEFAULT_PROGRESS_TEMPLATE);
+
+    let progress_chars = match settings.progress_style {
+        ProgressChars::Plain => "#>-",
+        ProgressChars::Unicode => "█▉▊▋▌▍▎▏  ",
+    };
+
+    Ok(ProgressStyle::with_template(template)?.progress_chars(progress_chars))
+}
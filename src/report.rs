@@ -0,0 +1,109 @@
+use crate::{commit::Commit, error::Error, language, sink::OutputSink};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{collections::HashMap, io::Write};
+
+// Totals for one language across everything walked. Files with no recognised
+// language are counted under a null language
+//
+#[derive(Serialize, Default)]
+struct LanguageTotals {
+    language: Option<&'static str>,
+    commits: u64,
+    files_changed: u64,
+    lines_added: u64,
+    lines_removed: u64,
+    churn: u64,
+}
+
+// Instead of writing out commits, adds up the churn for each language and writes
+// one line per language once everything has been walked, ranked by the chosen total
+// (languages that tie are in alphabetical order)
+//
+pub struct LanguageReport<W: Write> {
+    out: W,
+    sort: ReportSort,
+    order: SortOrder,
+    totals: HashMap<Option<&'static str>, LanguageTotals>,
+}
+
+impl<W: Write> LanguageReport<W> {
+    pub fn new(out: W, sort: ReportSort, order: SortOrder) -> LanguageReport<W> {
+        LanguageReport {
+            out,
+            sort,
+            order,
+            totals: HashMap::new(),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for LanguageReport<W> {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        let mut languages_seen = Vec::new();
+
+        for change in commit.changes.iter() {
+            let language = language::detect(&change.path);
+            let totals = self.totals.entry(language).or_insert(LanguageTotals {
+                language,
+                ..LanguageTotals::default()
+            });
+
+            totals.files_changed += 1;
+            totals.lines_added += u64::from(change.lines_added);
+            totals.lines_removed += u64::from(change.lines_removed);
+            totals.churn += u64::from(change.lines_added) + u64::from(change.lines_removed);
+
+            if !languages_seen.contains(&language) {
+                languages_seen.push(language);
+                totals.commits += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        let mut totals: Vec<LanguageTotals> = self.totals.drain().map(|(_, t)| t).collect();
+        totals.sort_by(|a, b| {
+            let ordering = match self.sort {
+                ReportSort::Churn => a.churn.cmp(&b.churn),
+                ReportSort::Commits => a.commits.cmp(&b.commits),
+                ReportSort::Name => a.language.cmp(&b.language),
+                ReportSort::Added => a.lines_added.cmp(&b.lines_added),
+                ReportSort::Removed => a.lines_removed.cmp(&b.lines_removed),
+            };
+            match self.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+            .then(a.language.cmp(&b.language))
+        });
+
+        for language_totals in totals.iter() {
+            let json = serde_json::to_string(language_totals)
+                .map_err(|e| Error::serde("Serde failed", e))?;
+            writeln!(self.out, "{}", json).map_err(|e| Error::io("Write failed", e))?;
+        }
+        self.out.flush().map_err(|e| Error::io("Write failed", e))
+    }
+}
+
+// What a --report's lines are ranked by
+//
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ReportSort {
+    // Lines added plus lines removed
+    Churn,
+    // Commits touching it at all
+    Commits,
+    // Its name (the language), alphabetically
+    Name,
+    Added,
+    Removed,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
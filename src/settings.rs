@@ -0,0 +1,231 @@
+use clap::ValueEnum;
+use regex::Regex;
+
+// What --detect-squash looks for in a subject by default - GitHub's "(#123)"
+//
+pub const DEFAULT_SQUASH_PATTERN: &str = r"\(#(\d+)\)$";
+
+// Everything that decides what's cloned, walked and extracted - the command line's
+// options without the ones about where and how the output is written. Start from
+// the default (what the command line does with no options) and change what's
+// needed:
+//
+//     let settings = Settings {
+//         all_branches: true,
+//         find_renames: true,
+//         ..Settings::default()
+//     };
+//
+#[derive(Debug, Clone)]
+pub struct Settings {
+    // Authentication. The password is taken as an access token with token_auth
+    // (--token-file), so it's sent without a username when there isn't one
+    //
+    pub plaintext_username: Option<String>,
+    pub plaintext_password: Option<String>,
+    pub token_auth: bool,
+    pub use_credential_helper: bool,
+    pub interactive: bool,
+    pub max_auth_attempts: u32,
+    pub ssh_key: Option<String>,
+    pub known_hosts: Option<String>,
+    pub insecure_skip_host_key_check: bool,
+
+    // Cloning. max_bandwidth is in bytes a second
+    //
+    pub mirror: bool,
+    pub remote: Option<String>,
+    pub recurse_submodules: bool,
+    pub max_bandwidth: Option<u64>,
+
+    // Which commits are walked and written
+    //
+    pub all_branches: bool,
+    pub before_commit: Option<String>,
+    pub after_commit: Option<String>,
+    pub chronological: bool,
+    pub streaming: bool,
+    pub stable_order: bool,
+    pub grep: Option<Regex>,
+    pub grep_invert: bool,
+    pub type_filter: TypeFilter,
+    pub on_missing_name: OnMissing,
+    pub on_missing_email: OnMissing,
+    pub include_uncommitted: bool,
+    pub no_diff: bool,
+    pub merge_parent: Option<usize>,
+
+    // What goes in each commit
+    //
+    pub repo_label: Option<String>,
+    pub with_repo_root: bool,
+    pub with_branches: bool,
+    pub with_sequence: bool,
+    pub with_encoding: bool,
+    pub split_message: bool,
+    pub date_source: DateSource,
+    pub abbrev: Option<u8>,
+    pub detect_squash: bool,
+    pub squash_pattern: Regex,
+    pub with_record_hash: bool,
+    pub emit_errors: bool,
+
+    // What's worked out for each file changed, and which files are kept
+    //
+    pub find_renames: bool,
+    pub find_copies: bool,
+    pub rename_threshold: u16,
+    pub attribute_renames: bool,
+    pub submodule_changes: bool,
+    pub with_sizes: bool,
+    pub word_diff: bool,
+    pub with_hunks: bool,
+    pub with_modified_hunk_lines: bool,
+    pub detect_language: bool,
+    pub sort_files: bool,
+    pub max_path_depth: Option<u32>,
+    pub ext: Vec<String>,
+    pub merge_duplicate_paths: bool,
+    pub strict: bool,
+    pub cache_dir: Option<String>,
+
+    // Progress, drawn on stderr (or progress_file)
+    //
+    pub progress_format: ProgressFormat,
+    pub progress_file: Option<String>,
+    pub progress_template: Option<String>,
+    pub progress_style: ProgressChars,
+    pub color: ColorChoice,
+
+    // How the repos are worked through
+    //
+    pub jobs: u16,
+    pub continue_on_error: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            plaintext_username: None,
+            plaintext_password: None,
+            token_auth: false,
+            use_credential_helper: false,
+            interactive: false,
+            max_auth_attempts: 5,
+            ssh_key: None,
+            known_hosts: None,
+            insecure_skip_host_key_check: false,
+            mirror: false,
+            remote: None,
+            recurse_submodules: false,
+            max_bandwidth: None,
+            all_branches: false,
+            before_commit: None,
+            after_commit: None,
+            chronological: false,
+            streaming: false,
+            stable_order: false,
+            grep: None,
+            grep_invert: false,
+            type_filter: TypeFilter::All,
+            on_missing_name: OnMissing::Placeholder,
+            on_missing_email: OnMissing::Placeholder,
+            include_uncommitted: false,
+            no_diff: false,
+            merge_parent: None,
+            repo_label: None,
+            with_repo_root: false,
+            with_branches: false,
+            with_sequence: false,
+            with_encoding: false,
+            split_message: false,
+            date_source: DateSource::Author,
+            abbrev: None,
+            detect_squash: false,
+            squash_pattern: Regex::new(DEFAULT_SQUASH_PATTERN).expect("valid squash pattern"),
+            with_record_hash: false,
+            emit_errors: false,
+            find_renames: false,
+            find_copies: false,
+            rename_threshold: 50,
+            attribute_renames: false,
+            submodule_changes: false,
+            with_sizes: false,
+            word_diff: false,
+            with_hunks: false,
+            with_modified_hunk_lines: false,
+            detect_language: false,
+            sort_files: true,
+            max_path_depth: None,
+            ext: Vec::new(),
+            merge_duplicate_paths: false,
+            strict: false,
+            cache_dir: None,
+            progress_format: ProgressFormat::Bars,
+            progress_file: None,
+            progress_template: None,
+            progress_style: ProgressChars::Plain,
+            color: ColorChoice::Auto,
+            jobs: 1,
+            continue_on_error: false,
+        }
+    }
+}
+
+// Which signature's time is used for the timestamp. They differ once a commit has
+// been rebased, amended or cherry-picked - the author time is when it was first
+// written, the committer time when it landed where it is now
+//
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum DateSource {
+    Author,
+    Committer,
+}
+
+// What's written for an author name or email that's missing
+//
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OnMissing {
+    // "unknown"
+    Placeholder,
+    Null,
+    // Leave the commit out altogether
+    Skip,
+}
+
+// Which kinds of commit are written, going by their CommitType
+//
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum TypeFilter {
+    All,
+    Normal,
+    Merge,
+}
+
+// How clone progress is reported on stderr
+//
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ProgressFormat {
+    // Progress bars for a human watching the terminal
+    Bars,
+    // One JSON object per line for a supervising process
+    Json,
+}
+
+// Characters used to draw the progress bars - plain ASCII works everywhere
+//
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ProgressChars {
+    Plain,
+    Unicode,
+}
+
+// Whether the progress bars are drawn in color
+//
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ColorChoice {
+    // Only when drawing to a terminal, and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
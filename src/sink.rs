@@ -0,0 +1,1088 @@
+use crate::{
+    commit::{Commit, ErrorRecord, FlatCommitRef},
+    diff::{ChangeStatus, FileChange},
+    error::Error,
+};
+use clap::ValueEnum;
+use rusqlite::{types::Value as SqlValue, Connection};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+// Anything that extracted commits can be written out to. The extraction loop
+// hands over each commit as it's produced and calls finish once everything
+// (across all repos) has been written
+//
+pub trait OutputSink {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error>;
+
+    // Error records don't have the columns every other record does, so only sinks
+    // that don't need them to can take one
+    //
+    fn write_error(&mut self, _error: &ErrorRecord) -> Result<(), Error> {
+        Err(Error::Config(
+            "--emit-errors only works with the ndjson format".to_string(),
+        ))
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error>;
+}
+
+// One JSON object per line - either one per file changed (flat) or one per commit
+// with its changes nested inside. With array set the records are instead written as
+// a single JSON array document, still one record per line unless pretty printed
+//
+pub struct NdjsonSink<W: Write> {
+    out: W,
+    nested: bool,
+    array: bool,
+    pretty: bool,
+    records_written: usize,
+}
+
+impl<W: Write> NdjsonSink<W> {
+    pub fn new(out: W, nested: bool, array: bool, pretty: bool) -> NdjsonSink<W> {
+        NdjsonSink {
+            out,
+            nested,
+            array,
+            pretty,
+            records_written: 0,
+        }
+    }
+
+    // The record as it's written - its line (or lines, pretty printed) along with
+    // whatever separates it from the record before. id is the commit the record is
+    // from, to say which one couldn't be serialized
+    //
+    fn format_record<T: Serialize>(&mut self, id: &str, record: &T) -> Result<String, Error> {
+        let json = if self.pretty {
+            serde_json::to_string_pretty(record)
+        } else {
+            serde_json::to_string(record)
+        }
+        .map_err(|e| Error::serde(format!("Serde failed for commit {}", id), e))?;
+
+        let record = match (self.array, self.records_written) {
+            (false, _) => json + "\n",
+            // Array elements are indented under the opening bracket
+            //
+            (true, n) => format!(
+                "{}  {}",
+                if n == 0 { "[\n" } else { ",\n" },
+                json.replace('\n', "\n  ")
+            ),
+        };
+
+        self.records_written += 1;
+        Ok(record)
+    }
+
+    // Whole records (newlines included) go out in a single write, and are flushed
+    // straight after. So even a writer shared with something else never has half a
+    // line in it, and an interrupted run leaves a file that either ends with all of
+    // a commit's rows or part way through its last line - what --resume relies on
+    //
+    fn write_records(&mut self, records: &str) -> Result<(), Error> {
+        self.out
+            .write_all(records.as_bytes())
+            .and_then(|()| self.out.flush())
+            .map_err(|e| Error::io("Write failed", e))
+    }
+}
+
+impl<W: Write> OutputSink for NdjsonSink<W> {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        if self.nested {
+            let record = self.format_record(&commit.id, commit)?;
+            return self.write_records(&record);
+        }
+
+        let mut rows = String::new();
+        for flat in commit.flat() {
+            rows.push_str(&self.format_record(&commit.id, &flat)?);
+        }
+        self.write_records(&rows)
+    }
+
+    fn write_error(&mut self, error: &ErrorRecord) -> Result<(), Error> {
+        let record = self.format_record(&error.repo_url, error)?;
+        self.write_records(&record)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        if self.array {
+            let close = if self.records_written == 0 {
+                "[]\n"
+            } else {
+                "\n]\n"
+            };
+            write!(self.out, "{}", close).map_err(|e| Error::io("Write failed", e))?;
+        }
+        self.out.flush().map_err(|e| Error::io("Write failed", e))
+    }
+}
+
+// The csv writer's own buffer is kept small - whatever it's writing to does the
+// real buffering, and this way the bytes reach it (and --rotate-bytes' count of
+// them) as each row is written rather than 8KiB at a time
+//
+const CSV_BUFFER: usize = 256;
+
+// Comma (or tab) separated, with a header row taken from the FlatCommitRef field names
+//
+pub struct CsvSink<W: Write> {
+    writer: csv::Writer<W>,
+    message_escape: Option<MessageEscape>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(out: W) -> CsvSink<W> {
+        CsvSink {
+            writer: csv::WriterBuilder::new()
+                .buffer_capacity(CSV_BUFFER)
+                .from_writer(out),
+            message_escape: None,
+        }
+    }
+
+    // TSV is never quoted, so tabs and newlines in anything that can hold free text
+    // - messages, names, URLs and paths - are escaped instead
+    //
+    pub fn tsv(out: W, message_escape: MessageEscape) -> CsvSink<W> {
+        CsvSink {
+            writer: csv::WriterBuilder::new()
+                .delimiter(b'\t')
+                .quote_style(csv::QuoteStyle::Never)
+                .buffer_capacity(CSV_BUFFER)
+                .from_writer(out),
+            message_escape: Some(message_escape),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        let Some(escape) = self.message_escape else {
+            for flat in commit.flat() {
+                self.writer
+                    .serialize(flat)
+                    .map_err(|e| Error::serde("CSV failed", e))?;
+            }
+            return Ok(());
+        };
+
+        // The ids and hashes are hex, and branches aren't written to csv / tsv at
+        // all, so everything else that's text is escaped - the commit's fields once,
+        // and each row's own as it's written
+        //
+        let repo_url = escape.escape(&commit.repo_url);
+        let repo_root = commit.repo_root.as_deref().map(|root| escape.escape(root));
+        let author_name = commit
+            .author_name
+            .as_deref()
+            .map(|name| escape.escape(name));
+        let author_email = commit
+            .author_email
+            .as_deref()
+            .map(|email| escape.escape(email));
+        let message = escape.escape(&commit.message);
+        let subject = commit
+            .subject
+            .as_deref()
+            .map(|subject| escape.escape(subject));
+        let body = commit.body.as_deref().map(|body| escape.escape(body));
+        let encoding = commit
+            .encoding
+            .as_deref()
+            .map(|encoding| escape.escape(encoding));
+
+        for flat in commit.flat() {
+            let path = escape.escape(flat.path);
+            let old_path = flat.old_path.map(|old_path| escape.escape(old_path));
+            let language = flat.language.map(|language| escape.escape(language));
+
+            self.writer
+                .serialize(FlatCommitRef {
+                    repo_url: &repo_url,
+                    repo_root: repo_root.as_deref(),
+                    author_name: author_name.as_deref(),
+                    author_email: author_email.as_deref(),
+                    message: &message,
+                    subject: subject.as_deref(),
+                    body: body.as_deref(),
+                    encoding: encoding.as_deref(),
+                    path: &path,
+                    old_path: old_path.as_deref(),
+                    language: language.as_deref(),
+                    ..flat
+                })
+                .map_err(|e| Error::serde("CSV failed", e))?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        self.writer
+            .flush()
+            .map_err(|e| Error::io("Write failed", e))
+    }
+}
+
+// Each row as a MessagePack map (keyed by field name), prefixed with its length in
+// bytes as a big-endian u32 so a reader can split the stream back into records
+//
+pub struct MsgpackSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> MsgpackSink<W> {
+    pub fn new(out: W) -> MsgpackSink<W> {
+        MsgpackSink { out }
+    }
+}
+
+impl<W: Write> OutputSink for MsgpackSink<W> {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        for flat in commit.flat() {
+            let bytes = rmp_serde::to_vec_named(&flat)
+                .map_err(|e| Error::serde("MessagePack failed", e))?;
+            let length = u32::try_from(bytes.len())
+                .map_err(|e| Error::serde("MessagePack record too large", e))?;
+
+            self.out
+                .write_all(&length.to_be_bytes())
+                .and_then(|_| self.out.write_all(&bytes))
+                .map_err(|e| Error::io("Write failed", e))?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        self.out.flush().map_err(|e| Error::io("Write failed", e))
+    }
+}
+
+// Rows go into a single flat_commits table. The columns are worked out from the
+// serialized FlatCommitRef rather than spelled out here, so new fields show up
+// without having to touch this sink
+//
+pub struct SqliteSink {
+    conn: Connection,
+    columns: Option<Vec<String>>,
+}
+
+impl SqliteSink {
+    pub fn new(path: &str) -> Result<SqliteSink, Error> {
+        let conn = Connection::open(path).map_err(sqlite_error)?;
+
+        // Everything goes in as a single transaction, committed in finish
+        //
+        conn.execute_batch("BEGIN").map_err(sqlite_error)?;
+
+        Ok(SqliteSink {
+            conn,
+            columns: None,
+        })
+    }
+
+    fn create_table(&self, columns: &[String]) -> Result<(), Error> {
+        let sql = format!(
+            "CREATE TABLE IF NOT EXISTS flat_commits ({})",
+            columns
+                .iter()
+                .map(|c| format!("\"{}\"", c))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        self.conn.execute(&sql, []).map_err(sqlite_error)?;
+        Ok(())
+    }
+}
+
+impl OutputSink for SqliteSink {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        for flat in commit.flat() {
+            let row = match serde_json::to_value(&flat) {
+                Ok(Value::Object(row)) => row,
+                Ok(_) => continue,
+                Err(e) => {
+                    return Err(Error::serde(
+                        format!("Serde failed for commit {}", commit.id),
+                        e,
+                    ))
+                }
+            };
+
+            let columns = match self.columns.take() {
+                Some(columns) => columns,
+                None => {
+                    let columns: Vec<String> = row.keys().cloned().collect();
+                    self.create_table(&columns)?;
+                    columns
+                }
+            };
+
+            let sql = format!(
+                "INSERT INTO flat_commits ({}) VALUES ({})",
+                columns
+                    .iter()
+                    .map(|c| format!("\"{}\"", c))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                vec!["?"; columns.len()].join(", ")
+            );
+            let values: Vec<SqlValue> = columns
+                .iter()
+                .map(|c| to_sql_value(row.get(c).unwrap_or(&Value::Null)))
+                .collect();
+
+            self.conn
+                .prepare_cached(&sql)
+                .and_then(|mut stmt| stmt.execute(rusqlite::params_from_iter(values)))
+                .map_err(sqlite_error)?;
+
+            self.columns = Some(columns);
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        self.conn.execute_batch("COMMIT").map_err(sqlite_error)
+    }
+}
+
+fn to_sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        // Anything nested is kept as its JSON text
+        //
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+fn sqlite_error(e: rusqlite::Error) -> Error {
+    Error::output("SQLite failed", e)
+}
+
+// POSTs each commit's rows to a URL as a JSON array - one request per commit
+//
+pub struct WebhookSink {
+    url: String,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> WebhookSink {
+        WebhookSink {
+            url: url.to_string(),
+        }
+    }
+}
+
+impl OutputSink for WebhookSink {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        let rows: Vec<FlatCommitRef> = commit.flat().collect();
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let body = serde_json::to_value(&rows).map_err(|e| Error::serde("Serde failed", e))?;
+        ureq::post(&self.url)
+            .send_json(body)
+            .map_err(|e| Error::output("Webhook failed", e))?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// A buffered output file that counts the bytes written to it. The count is shared,
+// so it can still be read once the file has been handed over to a sink
+//
+pub struct OutputFile {
+    out: BufWriter<File>,
+    written: Arc<AtomicU64>,
+}
+
+impl OutputFile {
+    fn create(path: &Path) -> Result<OutputFile, Error> {
+        let file = File::create(path)
+            .map_err(|e| Error::io(format!("Unable to create {}", path.display()), e))?;
+        Ok(OutputFile {
+            out: BufWriter::new(file),
+            written: Arc::new(AtomicU64::new(0)),
+        })
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.written.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Makes the sink that writes one of SplitSink's (or RotatingSink's) files
+//
+type NewSink<'w> = dyn Fn(OutputFile) -> Result<Box<dyn OutputSink + 'w>, Error> + 'w;
+
+// A file per branch in a directory, each written by its own sink - a commit goes
+// into the file of every branch it's on. Files are created the first time their
+// branch comes up
+//
+pub struct SplitSink<'w> {
+    dir: PathBuf,
+    extension: &'static str,
+    new_sink: Box<NewSink<'w>>,
+    sinks: HashMap<String, Box<dyn OutputSink + 'w>>,
+}
+
+impl<'w> SplitSink<'w> {
+    pub fn new(
+        dir: &str,
+        extension: &'static str,
+        new_sink: impl Fn(OutputFile) -> Result<Box<dyn OutputSink + 'w>, Error> + 'w,
+    ) -> Result<SplitSink<'w>, Error> {
+        fs::create_dir_all(dir)
+            .map_err(|e| Error::io(format!("Unable to create output dir {}", dir), e))?;
+
+        Ok(SplitSink {
+            dir: PathBuf::from(dir),
+            extension,
+            new_sink: Box::new(new_sink),
+            sinks: HashMap::new(),
+        })
+    }
+}
+
+impl OutputSink for SplitSink<'_> {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        for branch in commit.branches.iter().flatten() {
+            let sink = match self.sinks.get_mut(branch) {
+                Some(sink) => sink,
+                None => {
+                    let path =
+                        self.dir
+                            .join(format!("{}.{}", safe_file_name(branch), self.extension));
+                    let sink = (self.new_sink)(OutputFile::create(&path)?)?;
+                    self.sinks.entry(branch.clone()).or_insert(sink)
+                }
+            };
+            sink.write_commit(commit)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        for (_branch, sink) in self.sinks {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// Rolls over to a new numbered file (out.00001.ndjson, out.00002.ndjson, ...) once
+// the current one has reached rotate_bytes. Files are only ever switched between
+// commits, so neither a record nor a commit's rows are split across two files -
+// each file can go over the limit by up to one commit
+//
+pub struct RotatingSink<'w> {
+    path: PathBuf,
+    rotate_bytes: u64,
+    new_sink: Box<NewSink<'w>>,
+    current: Option<(Box<dyn OutputSink + 'w>, Arc<AtomicU64>)>,
+    files: u32,
+}
+
+impl<'w> RotatingSink<'w> {
+    pub fn new(
+        path: &str,
+        rotate_bytes: u64,
+        new_sink: impl Fn(OutputFile) -> Result<Box<dyn OutputSink + 'w>, Error> + 'w,
+    ) -> RotatingSink<'w> {
+        RotatingSink {
+            path: PathBuf::from(path),
+            rotate_bytes,
+            new_sink: Box::new(new_sink),
+            current: None,
+            files: 0,
+        }
+    }
+
+    fn next_file(&mut self) -> Result<(Box<dyn OutputSink + 'w>, Arc<AtomicU64>), Error> {
+        self.files += 1;
+
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match self.path.extension() {
+            Some(extension) => {
+                format!("{}.{:05}.{}", stem, self.files, extension.to_string_lossy())
+            }
+            None => format!("{}.{:05}", stem, self.files),
+        };
+
+        let file = OutputFile::create(&self.path.with_file_name(name))?;
+        let written = file.written.clone();
+        Ok(((self.new_sink)(file)?, written))
+    }
+
+    // Writes to the current file, starting a new one first if there isn't one, and
+    // finishes it once it's reached the size
+    //
+    fn write_with(
+        &mut self,
+        write: impl FnOnce(&mut dyn OutputSink) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let (mut sink, written) = match self.current.take() {
+            Some(current) => current,
+            None => self.next_file()?,
+        };
+        write(sink.as_mut())?;
+
+        if written.load(Ordering::Relaxed) >= self.rotate_bytes {
+            sink.finish()
+        } else {
+            self.current = Some((sink, written));
+            Ok(())
+        }
+    }
+}
+
+impl OutputSink for RotatingSink<'_> {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        self.write_with(|sink| sink.write_commit(commit))
+    }
+
+    fn write_error(&mut self, error: &ErrorRecord) -> Result<(), Error> {
+        self.write_with(|sink| sink.write_error(error))
+    }
+
+    // There's always at least one file, even with nothing to write
+    //
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        match self.current.take() {
+            Some((sink, _written)) => sink.finish(),
+            None if self.files == 0 => self.next_file()?.0.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Flat output is a row per file, so a commit without any (a merge, or an empty
+// commit) would leave no trace. Such a commit is passed on with a change standing
+// in for the commit itself - an empty path, unmodified and with zero counts - so
+// it gets a single row. Only what's written gets the extra change, not the commit
+// as it was extracted
+//
+pub struct CommitRowSink<'w> {
+    inner: Box<dyn OutputSink + 'w>,
+}
+
+impl<'w> CommitRowSink<'w> {
+    pub fn new(inner: Box<dyn OutputSink + 'w>) -> CommitRowSink<'w> {
+        CommitRowSink { inner }
+    }
+}
+
+impl OutputSink for CommitRowSink<'_> {
+    fn write_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        if !commit.changes.is_empty() {
+            return self.inner.write_commit(commit);
+        }
+        let mut with_row = commit.clone();
+        with_row.changes.push(FileChange {
+            status: ChangeStatus::Unmodified,
+            ..Default::default()
+        });
+        self.inner.write_commit(&with_row)
+    }
+
+    fn write_error(&mut self, error: &ErrorRecord) -> Result<(), Error> {
+        self.inner.write_error(error)
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        self.inner.finish()
+    }
+}
+
+// Branch names can have / in them (and anything else git allows), so everything
+// but letters, digits, '.', '-' and '_' is percent-encoded. Two branches can't end
+// up with the same file name that way, and feature/x becomes feature%2Fx
+//
+fn safe_file_name(name: &str) -> String {
+    let mut safe = String::new();
+    for byte in name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => safe.push(byte as char),
+            // Only a leading '.' needs encoding, so there's no hidden / .. file
+            b'.' if !safe.is_empty() => safe.push('.'),
+            _ => safe.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    safe
+}
+
+// How tabs / newlines in commit messages (and any other text) are made safe for TSV
+// output
+//
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum MessageEscape {
+    // Backslash escapes (\t, \n, \r and \\) which can be reversed
+    Backslash,
+    // Replace each of them with a single space
+    Space,
+}
+
+impl MessageEscape {
+    // Borrows the text back when there's nothing in it to escape, as is nearly
+    // always the case for everything but the message
+    //
+    fn escape<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let special: &[char] = match self {
+            MessageEscape::Backslash => &['\\', '\t', '\n', '\r'],
+            MessageEscape::Space => &['\t', '\n', '\r'],
+        };
+        if !text.contains(special) {
+            return Cow::Borrowed(text);
+        }
+
+        Cow::Owned(match self {
+            MessageEscape::Backslash => text
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r"),
+            MessageEscape::Space => text.replace(['\t', '\n', '\r'], " "),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commit::{tests::commit_changing, FlatCommit};
+    use std::{
+        io::{BufRead, BufReader, Read},
+        net::TcpListener,
+    };
+
+    fn write_commits(sink: impl OutputSink, commits: &[Commit]) -> Result<(), Error> {
+        let mut sink: Box<dyn OutputSink> = Box::new(sink);
+        for commit in commits {
+            sink.write_commit(commit)?;
+        }
+        sink.finish()
+    }
+
+    fn lines(out: &[u8]) -> Vec<Value> {
+        String::from_utf8_lossy(out)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn ndjson_writes_a_line_per_file_changed() {
+        let mut out = Vec::new();
+        write_commits(
+            NdjsonSink::new(&mut out, false, false, false),
+            &[
+                commit_changing(&["a.txt", "b.txt"]),
+                commit_changing(&["c.txt"]),
+            ],
+        )
+        .unwrap();
+
+        let rows = lines(&out);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1]["path"], "b.txt");
+        assert_eq!(rows[2]["message"], "Change some files");
+    }
+
+    #[test]
+    fn ndjson_nested_writes_a_line_per_commit() {
+        let mut out = Vec::new();
+        write_commits(
+            NdjsonSink::new(&mut out, true, false, false),
+            &[commit_changing(&["a.txt", "b.txt"])],
+        )
+        .unwrap();
+
+        let commits = lines(&out);
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0]["changes"][1]["path"], "b.txt");
+    }
+
+    #[test]
+    fn ndjson_array_is_one_json_document() {
+        let mut out = Vec::new();
+        write_commits(
+            NdjsonSink::new(&mut out, false, true, false),
+            &[commit_changing(&["a.txt", "b.txt"])],
+        )
+        .unwrap();
+
+        let rows: Vec<Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(rows.len(), 2);
+
+        let mut out = Vec::new();
+        write_commits(NdjsonSink::new(&mut out, false, true, false), &[]).unwrap();
+        assert_eq!(out, b"[]\n");
+    }
+
+    #[test]
+    fn csv_writes_a_header_then_a_row_per_file_changed() {
+        let mut out = Vec::new();
+        write_commits(
+            CsvSink::new(&mut out),
+            &[commit_changing(&["a.txt", "b.txt"])],
+        )
+        .unwrap();
+
+        let mut reader = csv::Reader::from_reader(&out[..]);
+        let headers = reader.headers().unwrap().clone();
+        let path = headers.iter().position(|header| header == "path").unwrap();
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(headers.get(0), Some("schema_version"));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].get(path), Some("b.txt"));
+    }
+
+    // Undoes MessageEscape::Backslash
+    //
+    fn unescape(text: &str) -> String {
+        let mut unescaped = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            unescaped.push(match c {
+                '\\' => match chars.next() {
+                    Some('t') => '\t',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    other => other.unwrap(),
+                },
+                c => c,
+            });
+        }
+        unescaped
+    }
+
+    // The unescaped TSV rows, as maps from each header to the field under it
+    //
+    fn tsv_rows(out: &[u8]) -> Vec<HashMap<String, String>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .quoting(false)
+            .from_reader(out);
+        let headers = reader.headers().unwrap().clone();
+        reader
+            .records()
+            .map(|row| {
+                let row = row.unwrap();
+                assert_eq!(row.len(), headers.len());
+                headers
+                    .iter()
+                    .zip(row.iter())
+                    .map(|(header, field)| (header.to_string(), unescape(field)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tsv_round_trips_tabs_and_newlines() {
+        let mut commit = commit_changing(&["tab\there.txt", "new\nline\\.txt"]);
+        commit.branches = None;
+        commit.message = "Subject\twith a tab\n\nAnd a body\\n\r\n".to_string();
+        commit.author_name = Some("Ada\tLovelace".to_string());
+        commit.changes[1].old_path = Some("old\r\npath".to_string());
+
+        let mut out = Vec::new();
+        write_commits(
+            CsvSink::tsv(&mut out, MessageEscape::Backslash),
+            &[commit.clone()],
+        )
+        .unwrap();
+
+        let rows = tsv_rows(&out);
+        assert_eq!(rows.len(), 2);
+        for (row, change) in rows.iter().zip(&commit.changes) {
+            assert_eq!(row["path"], change.path);
+            assert_eq!(row["message"], commit.message);
+            assert_eq!(row["author_name"], "Ada\tLovelace");
+        }
+        assert_eq!(rows[1]["old_path"], "old\r\npath");
+    }
+
+    #[test]
+    fn tsv_round_trips_a_split_message() {
+        let mut commit = commit_changing(&["a.txt"]);
+        commit.branches = None;
+        commit.message = "Fix\tthe thing\n\nLine one\nline two\n\nAnother\tparagraph\n".to_string();
+        commit.subject = Some("Fix\tthe thing".to_string());
+        commit.body = Some("Line one\nline two\n\nAnother\tparagraph".to_string());
+        commit.message_length = Some(commit.message.chars().count());
+
+        let mut out = Vec::new();
+        write_commits(CsvSink::tsv(&mut out, MessageEscape::Backslash), &[commit]).unwrap();
+
+        let rows = tsv_rows(&out);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["subject"], "Fix\tthe thing");
+        assert_eq!(rows[0]["body"], "Line one\nline two\n\nAnother\tparagraph");
+        assert_eq!(rows[0]["message_length"], "52");
+    }
+
+    #[test]
+    fn tsv_can_replace_tabs_and_newlines_with_spaces() {
+        let mut commit = commit_changing(&["tab\there.txt"]);
+        commit.branches = None;
+        commit.message = "Two\nlines".to_string();
+
+        let mut out = Vec::new();
+        write_commits(CsvSink::tsv(&mut out, MessageEscape::Space), &[commit]).unwrap();
+
+        let rows = tsv_rows(&out);
+        assert_eq!(rows[0]["path"], "tab here.txt");
+        assert_eq!(rows[0]["message"], "Two lines");
+    }
+
+    // Splits a MessagePack stream back into its rows
+    //
+    fn msgpack_rows(mut out: &[u8]) -> Vec<FlatCommit> {
+        let mut rows = Vec::new();
+        while !out.is_empty() {
+            let (length, rest) = out.split_at(4);
+            let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+            let (row, rest) = rest.split_at(length);
+            rows.push(rmp_serde::from_slice(row).unwrap());
+            out = rest;
+        }
+        rows
+    }
+
+    #[test]
+    fn msgpack_writes_a_length_prefixed_map_per_file_changed() {
+        let mut out = Vec::new();
+        write_commits(
+            MsgpackSink::new(&mut out),
+            &[
+                commit_changing(&["a.txt", "b.txt"]),
+                commit_changing(&["c.txt"]),
+            ],
+        )
+        .unwrap();
+
+        let rows = msgpack_rows(&out);
+        let paths: Vec<&str> = rows.iter().map(|row| row.path.as_str()).collect();
+        assert_eq!(paths, ["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(rows[2].message, "Change some files");
+        assert_eq!(rows[2].branches, Some(vec!["main".to_string()]));
+    }
+
+    #[test]
+    fn sqlite_inserts_a_row_per_file_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.sqlite");
+        write_commits(
+            SqliteSink::new(path.to_str().unwrap()).unwrap(),
+            &[
+                commit_changing(&["a.txt", "b.txt"]),
+                commit_changing(&["c.txt"]),
+            ],
+        )
+        .unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let paths: Vec<String> = conn
+            .prepare("SELECT path FROM flat_commits ORDER BY path")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(paths, ["a.txt", "b.txt", "c.txt"]);
+
+        let lines_added: i64 = conn
+            .query_row("SELECT SUM(lines_added) FROM flat_commits", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(lines_added, 3);
+    }
+
+    // Takes a single request, answering it with an empty 200, and hands back its body
+    //
+    fn webhook_server() -> (String, std::thread::JoinHandle<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/commits", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            (&stream)
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            serde_json::from_slice(&body).unwrap()
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn webhook_posts_each_commits_rows_as_an_array() {
+        let (url, server) = webhook_server();
+        write_commits(
+            WebhookSink::new(&url),
+            &[commit_changing(&["a.txt", "b.txt"])],
+        )
+        .unwrap();
+
+        let body = server.join().unwrap();
+        let rows = body.as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["path"], "a.txt");
+    }
+
+    #[test]
+    fn webhook_failure_is_an_output_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/commits", listener.local_addr().unwrap());
+        drop(listener);
+
+        let result = write_commits(WebhookSink::new(&url), &[commit_changing(&["a.txt"])]);
+        assert!(matches!(result, Err(Error::Output { .. })));
+    }
+
+    // A writer that refuses every write
+    //
+    struct BrokenWriter;
+
+    impl Write for BrokenWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // A record that can never be serialized
+    //
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("not today"))
+        }
+    }
+
+    #[test]
+    fn failing_writes_are_io_errors() {
+        let result = write_commits(
+            NdjsonSink::new(BrokenWriter, false, false, false),
+            &[commit_changing(&["a.txt"])],
+        );
+
+        match result {
+            Err(error @ Error::Io { .. }) => {
+                assert_eq!(error.kind(), "io");
+                let source = std::error::Error::source(&error).unwrap();
+                assert_eq!(source.to_string(), "disk full");
+            }
+            other => panic!("expected an Io error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failing_serialization_is_a_serde_error() {
+        let mut sink = NdjsonSink::new(Vec::new(), false, false, false);
+
+        match sink.format_record("4b825dc", &Unserializable) {
+            Err(error @ Error::Serde { .. }) => {
+                assert_eq!(error.kind(), "serde");
+                let source = std::error::Error::source(&error).unwrap();
+                assert_eq!(source.to_string(), "not today");
+            }
+            other => panic!("expected a Serde error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn records_that_cant_be_serialized_fail_rather_than_going_missing() {
+        let mut out = Vec::new();
+        let mut sink = NdjsonSink::new(&mut out, false, true, false);
+
+        let error = sink.format_record("4b825dc", &Unserializable).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Serde failed for commit 4b825dc: not today"
+        );
+
+        // Nothing was counted as written, so the array still starts properly
+        //
+        sink.write_commit(&commit_changing(&["a.txt"])).unwrap();
+        Box::new(sink).finish().unwrap();
+        let records: Vec<Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn commit_row_writes_one_row_for_a_commit_without_changes() {
+        let changeless = commit_changing(&[]);
+        let changed = commit_changing(&["a.txt", "b.txt"]);
+
+        let mut out = Vec::new();
+        write_commits(
+            CommitRowSink::new(Box::new(NdjsonSink::new(&mut out, false, false, false))),
+            &[changeless.clone(), changed],
+        )
+        .unwrap();
+
+        let rows = lines(&out);
+        let paths: Vec<&Value> = rows.iter().map(|row| &row["path"]).collect();
+        assert_eq!(paths, ["", "a.txt", "b.txt"]);
+        assert_eq!(rows[0]["id"], changeless.id);
+        assert_eq!(rows[0]["status"], "unmodified");
+        assert_eq!(rows[0]["lines_added"], 0);
+        assert!(changeless.changes.is_empty());
+    }
+}
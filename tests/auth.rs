@@ -0,0 +1,179 @@
+mod common;
+
+use common::{basic_auth, command, AuthServer};
+use std::{os::unix::fs::PermissionsExt, path::Path};
+
+// Writes an executable shell script into dir
+//
+fn script(dir: &Path, name: &str, body: &str) {
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn credential_helper_supplies_the_password() {
+    let bin = tempfile::tempdir().unwrap();
+    script(
+        bin.path(),
+        "git-credential-stub",
+        r#"[ "$1" = get ] && printf 'username=stub-user\npassword=stub-pass\n'"#,
+    );
+    let path = format!(
+        "{}:{}",
+        bin.path().display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let server = AuthServer::start();
+
+    let output = command()
+        .args(["--progress-format", "json", "--progress-file", "/dev/null"])
+        .args(["--use-credential-helper", &server.url])
+        .env("PATH", path)
+        .env("GIT_CONFIG_COUNT", "1")
+        .env("GIT_CONFIG_KEY_0", "credential.helper")
+        .env("GIT_CONFIG_VALUE_0", "stub")
+        .output()
+        .unwrap();
+
+    let authorizations = server.stop();
+    assert!(!output.status.success());
+    assert_eq!(authorizations[0], None);
+    assert_eq!(
+        authorizations[1],
+        Some(basic_auth("stub-user", "stub-pass"))
+    );
+}
+
+#[test]
+fn askpass_is_asked_for_the_username_and_password() {
+    let bin = tempfile::tempdir().unwrap();
+    let prompts = bin.path().join("prompts");
+    script(
+        bin.path(),
+        "askpass",
+        &format!(
+            r#"echo "$1" >> {}
+case "$1" in Username*) echo ask-user ;; *) echo ask-pass ;; esac"#,
+            prompts.display()
+        ),
+    );
+    let server = AuthServer::start();
+    let url = server.url.clone();
+
+    let output = command()
+        .args(["--progress-format", "json", "--progress-file", "/dev/null"])
+        .arg(&url)
+        .env("GIT_ASKPASS", bin.path().join("askpass"))
+        .output()
+        .unwrap();
+
+    let authorizations = server.stop();
+    assert!(!output.status.success());
+    assert_eq!(authorizations[1], Some(basic_auth("ask-user", "ask-pass")));
+    let prompts = std::fs::read_to_string(prompts).unwrap();
+    assert!(
+        prompts.starts_with(&format!("Username for '{url}': \nPassword for '{url}': \n")),
+        "{}",
+        prompts
+    );
+}
+
+#[test]
+fn without_interactive_missing_credentials_are_an_error_not_a_prompt() {
+    let server = AuthServer::start();
+    let url = server.url.clone();
+
+    let output = command()
+        .args(["--progress-format", "json", "--progress-file", "/dev/null"])
+        .arg(&url)
+        .env_remove("GIT_ASKPASS")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+
+    server.stop();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "{} asked for a username and password, but none were given - pass -U and -P, use --use-credential-helper or GIT_ASKPASS, or --interactive to be prompted",
+            url
+        )),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("Password for"), "{}", stderr);
+}
+
+#[test]
+fn rejected_credentials_are_only_tried_max_auth_attempts_times() {
+    let server = AuthServer::start();
+
+    let output = command()
+        .args(["--progress-format", "json", "--progress-file", "/dev/null"])
+        .args([
+            "-U",
+            "user",
+            "-P",
+            "wrong",
+            "--max-auth-attempts",
+            "2",
+            &server.url,
+        ])
+        .output()
+        .unwrap();
+
+    let authorizations = server.stop();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("giving up after --max-auth-attempts 2"),
+        "{}",
+        stderr
+    );
+    let sent = authorizations
+        .iter()
+        .filter(|header| header.is_some())
+        .count();
+    assert_eq!(sent, 2);
+}
+
+#[test]
+fn token_file_is_sent_as_the_password() {
+    let server = AuthServer::start();
+    let token_file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(token_file.path(), "  s3cret-token\n").unwrap();
+
+    let output = command()
+        .args(["--progress-format", "json", "--progress-file", "/dev/null"])
+        .args(["--max-auth-attempts", "1", "--token-file"])
+        .arg(token_file.path())
+        .arg(&server.url)
+        .output()
+        .unwrap();
+
+    let authorizations = server.stop();
+    assert!(!output.status.success());
+    assert!(authorizations.contains(&Some(basic_auth("x-access-token", "s3cret-token"))));
+}
+
+#[test]
+fn missing_or_empty_secret_files_fail_up_front() {
+    let empty = tempfile::NamedTempFile::new().unwrap();
+    let empty = empty.path().to_str().unwrap();
+
+    for (args, error) in [
+        (
+            ["--token-file", "/nonexistent/token"],
+            "Unable to read --token-file /nonexistent/token",
+        ),
+        (["--password-file", empty], "Unable to read --password-file"),
+    ] {
+        let output = common::run(&[&args[..], &["https://example.com/repo.git"]].concat());
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(error), "{}", stderr);
+        assert!(!stderr.contains("Clone"), "{}", stderr);
+    }
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{run_records, Fixture};
+use std::{fs, path::Path};
+
+// Every file under dir
+//
+fn files(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(self::files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+#[test]
+fn second_run_reads_the_diffs_from_the_cache() {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "one\n");
+    fixture.commit("First");
+    fixture.write("a.txt", "one\ntwo\n");
+    fixture.commit("Second");
+    let cache = tempfile::tempdir().unwrap();
+    let cache_dir = cache.path().to_str().unwrap();
+
+    let first = run_records(&["--cache-dir", cache_dir, fixture.path()]);
+    let cached = files(cache.path());
+    assert_eq!(cached.len(), 2);
+
+    // Doctor the cached changes - the second run can only see this if it read them
+    // rather than diffing again
+    //
+    for path in &cached {
+        let contents = fs::read_to_string(path).unwrap();
+        fs::write(
+            path,
+            contents.replace("\"lines_added\":1", "\"lines_added\":999"),
+        )
+        .unwrap();
+    }
+    let second = run_records(&["--cache-dir", cache_dir, fixture.path()]);
+
+    assert_eq!(first.len(), second.len());
+    for (first, second) in first.iter().zip(&second) {
+        assert_eq!(first["lines_added"], 1);
+        assert_eq!(second["lines_added"], 999);
+        assert_eq!(first["id"], second["id"]);
+    }
+
+    // Different diff options are cached apart
+    //
+    let with_sizes = run_records(&["--cache-dir", cache_dir, "--with-sizes", fixture.path()]);
+    assert_eq!(with_sizes[0]["lines_added"], 1);
+    assert_eq!(files(cache.path()).len(), 4);
+}
+
+#[test]
+fn no_diff_never_diffs() {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "one\n");
+    fixture.commit("First");
+    fixture.write("a.txt", "one\ntwo\n");
+    fixture.commit("Second");
+    let cache = tempfile::tempdir().unwrap();
+    let cache_dir = cache.path().to_str().unwrap();
+
+    // Every diff made is cached, so an empty cache means there weren't any
+    //
+    let records = run_records(&["--cache-dir", cache_dir, "--no-diff", fixture.path()]);
+    assert!(files(cache.path()).is_empty());
+    assert_eq!(records.len(), 2);
+    for record in &records {
+        assert_eq!(record["changes"], serde_json::json!([]));
+        assert_eq!(record["type"], "Normal");
+        assert!(record.get("path").is_none());
+    }
+
+    run_records(&["--cache-dir", cache_dir, fixture.path()]);
+    assert_eq!(files(cache.path()).len(), 2);
+}
@@ -0,0 +1,755 @@
+mod common;
+
+use common::{run_records, Fixture};
+use serde_json::Value;
+
+// The row for path in a run's output, for the commit with the given id
+//
+fn change<'a>(records: &'a [Value], id: &str, path: &str) -> &'a Value {
+    records
+        .iter()
+        .find(|record| record["id"] == id && record["path"] == path)
+        .unwrap_or_else(|| panic!("no row for {} in {}", path, id))
+}
+
+#[test]
+fn submodule_pointer_bumps_record_both_commits() {
+    let fixture = Fixture::new();
+    let old = "1111111111111111111111111111111111111111";
+    let new = "2222222222222222222222222222222222222222";
+
+    // A gitlink straight into the index - the submodule doesn't need to exist to
+    // bump the pointer to it, just an empty directory for it like an uninitialised
+    // one has
+    //
+    std::fs::create_dir(std::path::Path::new(fixture.path()).join("lib")).unwrap();
+    fixture.git(&[
+        "update-index",
+        "--add",
+        "--cacheinfo",
+        &format!("160000,{},lib", old),
+    ]);
+    fixture.commit("Add lib");
+    fixture.git(&[
+        "update-index",
+        "--cacheinfo",
+        &format!("160000,{},lib", new),
+    ]);
+    let bump = fixture.commit("Bump lib");
+
+    let records = run_records(&["--submodule-changes", fixture.path()]);
+
+    let lib = change(&records, &bump, "lib");
+    assert_eq!(lib["status"], "modified");
+    assert_eq!(lib["submodule_old_id"], old);
+    assert_eq!(lib["submodule_new_id"], new);
+
+    let records = run_records(&[fixture.path()]);
+    assert_eq!(
+        change(&records, &bump, "lib")["submodule_old_id"],
+        Value::Null
+    );
+}
+
+#[test]
+fn lfs_pointers_are_found_in_a_mirror() {
+    let fixture = Fixture::new();
+    fixture.write("sub/.gitattributes", "*.bin filter=lfs -text\n");
+    fixture.write(
+        "sub/big.bin",
+        "version https://git-lfs.github.com/spec/v1\n\
+         oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+         size 12345\n",
+    );
+    fixture.write("top.bin", "not under sub\n");
+    let id = fixture.commit("Add a big file");
+
+    let records = run_records(&["--mirror", fixture.path()]);
+
+    let big = change(&records, &id, "sub/big.bin");
+    assert_eq!(big["lfs"], true);
+    assert_eq!(big["lfs_size"], 12345);
+    assert_eq!(change(&records, &id, "top.bin")["lfs"], false);
+}
+
+#[test]
+fn lfs_pointers_are_marked_with_the_real_size() {
+    let fixture = Fixture::new();
+    fixture.write(
+        ".gitattributes",
+        "*.bin filter=lfs diff=lfs merge=lfs -text\n",
+    );
+    fixture.write(
+        "big.bin",
+        "version https://git-lfs.github.com/spec/v1\n\
+         oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+         size 12345\n",
+    );
+    fixture.write("small.txt", "not in lfs\n");
+    let id = fixture.commit("Add a big file");
+
+    let records = run_records(&[fixture.path()]);
+
+    let big = change(&records, &id, "big.bin");
+    assert_eq!(big["lfs"], true);
+    assert_eq!(big["lfs_size"], 12345);
+    let small = change(&records, &id, "small.txt");
+    assert_eq!(small["lfs"], false);
+    assert_eq!(small["lfs_size"], Value::Null);
+}
+
+#[test]
+fn sizes_are_given_for_added_and_modified_files() {
+    let fixture = Fixture::new();
+    fixture.write("grows.txt", "12345\n");
+    fixture.commit("First");
+    fixture.write("grows.txt", "1234567890\n");
+    fixture.write("new.txt", "abc\n");
+    let id = fixture.commit("Second");
+
+    let records = run_records(&["--with-sizes", fixture.path()]);
+
+    let grows = change(&records, &id, "grows.txt");
+    assert_eq!(grows["old_size"], 6);
+    assert_eq!(grows["new_size"], 11);
+    let new = change(&records, &id, "new.txt");
+    assert_eq!(new["old_size"], 0);
+    assert_eq!(new["new_size"], 4);
+}
+
+#[test]
+fn renames_carry_their_source_path() {
+    let fixture = Fixture::new();
+    fixture.write("before.txt", "one\ntwo\nthree\nfour\nfive\n");
+    fixture.write("edited.txt", "one\n");
+    fixture.commit("First");
+    fixture.git(&["mv", "before.txt", "after.txt"]);
+    fixture.write("edited.txt", "one\ntwo\n");
+    let id = fixture.commit("Rename a file");
+
+    let records = run_records(&["--find-renames", fixture.path()]);
+
+    let renamed = change(&records, &id, "after.txt");
+    assert_eq!(renamed["status"], "renamed");
+    assert_eq!(renamed["old_path"], "before.txt");
+    assert_eq!(change(&records, &id, "edited.txt")["old_path"], Value::Null);
+}
+
+// Twenty numbered lines, far enough apart at the ends that changing both makes
+// two hunks
+//
+fn numbered_lines(line: impl Fn(u32) -> String) -> String {
+    (1..=20).map(|n| line(n) + "\n").collect()
+}
+
+#[test]
+fn hunks_give_where_each_change_landed() {
+    let fixture = Fixture::new();
+    fixture.write("file.txt", numbered_lines(|n| n.to_string()));
+    fixture.commit("First");
+    fixture.write(
+        "file.txt",
+        numbered_lines(|n| match n {
+            2 => "two".to_string(),
+            18 => "18\n18 and a half".to_string(),
+            n => n.to_string(),
+        }),
+    );
+    let id = fixture.commit("Change both ends");
+
+    let records = run_records(&["--with-hunks", fixture.path()]);
+
+    let hunks = &change(&records, &id, "file.txt")["hunks"];
+    assert_eq!(
+        hunks,
+        &serde_json::json!([
+            { "old_start": 1, "old_lines": 5, "new_start": 1, "new_lines": 5 },
+            { "old_start": 16, "old_lines": 5, "new_start": 16, "new_lines": 6 },
+        ])
+    );
+}
+
+#[test]
+fn hunks_are_classified_by_the_lines_they_change() {
+    let fixture = Fixture::new();
+    let original = numbered_lines(|n| n.to_string());
+    for file in ["appended.txt", "deleted.txt", "edited.txt"] {
+        fixture.write(file, &original);
+    }
+    fixture.commit("First");
+    fixture.write("appended.txt", original.clone() + "21\n");
+    fixture.write("deleted.txt", original.replace("10\n", ""));
+    fixture.write("edited.txt", original.replace("10\n", "ten\n"));
+    let id = fixture.commit("One hunk of each");
+
+    let records = run_records(&[fixture.path()]);
+
+    let hunks = |path| {
+        let row = change(&records, &id, path);
+        [
+            &row["hunks_added"],
+            &row["hunks_removed"],
+            &row["hunks_modified"],
+        ]
+        .map(|n| n.as_u64().unwrap())
+    };
+    assert_eq!(hunks("appended.txt"), [1, 0, 0]);
+    assert_eq!(hunks("deleted.txt"), [0, 1, 0]);
+    assert_eq!(hunks("edited.txt"), [0, 0, 1]);
+}
+
+// A file changed in a commit - (id, path) - and its (added, removed) line counts,
+// None for binary files
+//
+type LineCounts = ((String, String), Option<(u64, u64)>);
+
+// Every file's line counts in every commit, as git log --numstat gives them
+//
+fn numstat(fixture: &Fixture) -> Vec<LineCounts> {
+    let log = fixture.git(&["log", "--numstat", "--no-renames", "--format=commit %H"]);
+    let mut id = String::new();
+    let mut counts = Vec::new();
+
+    for line in log.lines().filter(|line| !line.is_empty()) {
+        if let Some(commit) = line.strip_prefix("commit ") {
+            id = commit.to_string();
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(3, '\t').collect();
+        let lines = match (fields[0].parse(), fields[1].parse()) {
+            (Ok(added), Ok(removed)) => Some((added, removed)),
+            _ => None,
+        };
+        counts.push(((id.clone(), fields[2].to_string()), lines));
+    }
+    counts.sort();
+    counts
+}
+
+#[test]
+fn line_counts_match_git_numstat() {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", numbered_lines(|n| n.to_string()));
+    fixture.write("image.png", b"\x89PNG\r\n\x1a\n\0\0\0binary");
+    fixture.commit("First");
+    fixture.write("a.txt", numbered_lines(|n| format!("{}\n", n * 2)));
+    fixture.write("no-newline.txt", "no newline at the end");
+    fixture.write("image.png", b"\x89PNG\r\n\x1a\n\0\0\0changed");
+    fixture.commit("Second");
+    fixture.write("no-newline.txt", "no newline at the end\n");
+    fixture.write("a.txt", "");
+    fixture.remove("image.png");
+    fixture.commit("Third");
+
+    let records = run_records(&[fixture.path()]);
+
+    // Binary files are counted as 0 lines where numstat gives "-"
+    //
+    let mut ours: Vec<LineCounts> = records
+        .iter()
+        .map(|record| {
+            let id = record["id"].as_str().unwrap().to_string();
+            let path = record["path"].as_str().unwrap().to_string();
+            let counts = (
+                record["lines_added"].as_u64().unwrap(),
+                record["lines_removed"].as_u64().unwrap(),
+            );
+            if record["binary"] == true {
+                assert_eq!(counts, (0, 0), "{}", record);
+                ((id, path), None)
+            } else {
+                ((id, path), Some(counts))
+            }
+        })
+        .collect();
+    ours.sort();
+
+    assert_eq!(ours, numstat(&fixture));
+}
+
+#[test]
+fn gitattributes_decide_what_is_binary() {
+    let fixture = Fixture::new();
+    fixture.write(".gitattributes", "*.lock -diff\n*.dat diff\n");
+    fixture.write("Cargo.lock", "plain text\n");
+    fixture.write("data.dat", "looks\0binary\n");
+    let id = fixture.commit("Add files with attributes");
+
+    let records = run_records(&[fixture.path()]);
+
+    let lock = change(&records, &id, "Cargo.lock");
+    assert_eq!(lock["binary"], true);
+    assert_eq!(lock["lines_added"], 0);
+    assert_eq!(lock["lines_removed"], 0);
+    let data = change(&records, &id, "data.dat");
+    assert_eq!(data["binary"], false);
+    assert_eq!(data["lines_added"], 1);
+}
+
+#[test]
+fn gitattributes_in_each_commit_decide_what_is_binary_in_a_mirror() {
+    let fixture = Fixture::new();
+    fixture.write("early.dat", "one\ntwo\n");
+    let early = fixture.commit("Before any attributes");
+    fixture.write(".gitattributes", "*.dat -diff\n");
+    fixture.write("late.dat", "one\ntwo\n");
+    let late = fixture.commit("Mark .dat as binary");
+
+    let records = run_records(&["--mirror", fixture.path()]);
+
+    let early = change(&records, &early, "early.dat");
+    assert_eq!(early["binary"], false);
+    assert_eq!(early["lines_added"], 2);
+    let late = change(&records, &late, "late.dat");
+    assert_eq!(late["binary"], true);
+    assert_eq!(late["lines_added"], 0);
+    assert_eq!(late["hunks_added"], 0);
+}
+
+#[test]
+fn a_file_becoming_a_symlink_is_one_typechange_row() {
+    let fixture = Fixture::new();
+    fixture.write("target.txt", "target\n");
+    fixture.write("link", "a file for now\n");
+    fixture.commit("First");
+    fixture.remove("link");
+    std::os::unix::fs::symlink(
+        "target.txt",
+        std::path::Path::new(fixture.path()).join("link"),
+    )
+    .unwrap();
+    let id = fixture.commit("Make it a link");
+
+    let records = run_records(&["--merge-duplicate-paths", fixture.path()]);
+
+    let rows: Vec<&Value> = records.iter().filter(|record| record["id"] == id).collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["path"], "link");
+    assert_eq!(rows[0]["status"], "typechange");
+}
+
+#[test]
+fn a_type_change_has_no_line_counts_either_way() {
+    let fixture = Fixture::new();
+    fixture.write("target.txt", "target\n");
+    fixture.write("link", "several\nlines\nof\ntext\n");
+    fixture.commit("First");
+    fixture.remove("link");
+    std::os::unix::fs::symlink(
+        "target.txt",
+        std::path::Path::new(fixture.path()).join("link"),
+    )
+    .unwrap();
+    let to_link = fixture.commit("Make it a link");
+    fixture.remove("link");
+    fixture.write("link", "a file\nagain\n");
+    let to_file = fixture.commit("Make it a file again");
+
+    let records = run_records(&[fixture.path()]);
+
+    for id in [&to_link, &to_file] {
+        let rows: Vec<&Value> = records
+            .iter()
+            .filter(|record| record["id"] == *id)
+            .collect();
+        assert_eq!(rows.len(), 1);
+        let row = rows[0];
+        assert_eq!(row["path"], "link");
+        assert_eq!(row["status"], "typechange");
+        for count in [
+            "lines_added",
+            "lines_removed",
+            "lines_modified",
+            "hunks_added",
+            "hunks_removed",
+            "hunks_modified",
+        ] {
+            assert_eq!(row[count], 0, "{} of {}", count, id);
+        }
+    }
+}
+
+#[test]
+fn word_diff_counts_the_words_changed_in_a_line() {
+    let fixture = Fixture::new();
+    fixture.write("prose.md", "The quick brown fox jumps over the lazy dog\n");
+    fixture.commit("First");
+    fixture.write(
+        "prose.md",
+        "The quick red fox jumps over the very lazy dog\n",
+    );
+    let id = fixture.commit("Edit a word or two");
+
+    let records = run_records(&["--word-diff", fixture.path()]);
+
+    let prose = change(&records, &id, "prose.md");
+    assert_eq!(prose["lines_added"], 1);
+    assert_eq!(prose["lines_removed"], 1);
+    assert_eq!(prose["words_added"], 2);
+    assert_eq!(prose["words_removed"], 1);
+
+    let records = run_records(&[fixture.path()]);
+    assert_eq!(
+        change(&records, &id, "prose.md")["words_added"],
+        Value::Null
+    );
+}
+
+// A merge of a feature branch into a main branch that moved on meanwhile, giving
+// back the fixture and the merge's id
+//
+fn merged_repo() -> (Fixture, String) {
+    let fixture = Fixture::new();
+    fixture.write("shared.txt", "shared\n");
+    fixture.commit("Start");
+    fixture.git(&["checkout", "--quiet", "-b", "feature"]);
+    fixture.write("feature.txt", "feature\n");
+    fixture.commit("Add a feature");
+    fixture.git(&["checkout", "--quiet", "main"]);
+    fixture.write("main.txt", "main\n");
+    fixture.commit("Carry on on main");
+    let merge = fixture.merge("feature", "Merge the feature");
+    (fixture, merge)
+}
+
+fn merge_paths(records: &[Value], merge: &str) -> Vec<String> {
+    records
+        .iter()
+        .filter(|record| record["id"] == merge)
+        .filter_map(|record| record["path"].as_str().map(str::to_string))
+        .collect()
+}
+
+#[test]
+fn merges_are_diffed_against_the_parent_asked_for() {
+    let (fixture, merge) = merged_repo();
+
+    let records = run_records(&[fixture.path()]);
+    assert!(merge_paths(&records, &merge).is_empty());
+
+    for args in [
+        &["--merge-parent=0"][..],
+        &["--merge-parent", "--detect-language"],
+    ] {
+        let records = run_records(&[args, &[fixture.path()]].concat());
+        assert_eq!(merge_paths(&records, &merge), ["feature.txt"], "{:?}", args);
+    }
+
+    let records = run_records(&["--merge-parent=1", fixture.path()]);
+    assert_eq!(merge_paths(&records, &merge), ["main.txt"]);
+
+    let output = common::run(&["--merge-parent=2", fixture.path()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("out of range"));
+}
+
+#[test]
+fn languages_are_only_detected_when_asked() {
+    let fixture = Fixture::new();
+    fixture.write("main.rs", "fn main() {}\n");
+    fixture.write("Makefile", "all:\n");
+    fixture.write("data.xyz", "?\n");
+    fixture.commit("Add some files");
+
+    let records = run_records(&["--detect-language", fixture.path()]);
+    let languages: Vec<(&str, &Value)> = records
+        .iter()
+        .map(|record| (record["path"].as_str().unwrap(), &record["language"]))
+        .collect();
+    assert_eq!(
+        languages,
+        [
+            ("Makefile", &Value::from("Makefile")),
+            ("data.xyz", &Value::Null),
+            ("main.rs", &Value::from("Rust")),
+        ]
+    );
+
+    let records = run_records(&[fixture.path()]);
+    assert!(records.iter().all(|record| record["language"].is_null()));
+}
+
+#[cfg(unix)]
+#[test]
+fn odd_file_names_are_written_as_git_stores_them() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let fixture = Fixture::new();
+    fixture.write("back\\slash.txt", "\\\n");
+    let name = std::ffi::OsStr::from_bytes(b"bad\xff.txt");
+    std::fs::write(std::path::Path::new(fixture.path()).join(name), "bad\n").unwrap();
+    fixture.commit("Add oddly named files");
+
+    let records = run_records(&[fixture.path()]);
+
+    let paths: Vec<&str> = records
+        .iter()
+        .map(|record| record["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["back\\slash.txt", "bad\u{fffd}.txt"]);
+}
+
+#[test]
+fn attribute_renames_gives_how_similar_a_rename_is() {
+    let fixture = Fixture::new();
+    let lines = numbered_lines(|n| format!("line {}", n));
+    fixture.write("pure.txt", &lines);
+    fixture.write("edited.txt", &lines);
+    fixture.commit("First");
+    fixture.git(&["mv", "pure.txt", "pure-moved.txt"]);
+    fixture.git(&["mv", "edited.txt", "edited-moved.txt"]);
+    fixture.write("edited-moved.txt", lines.replace("line 10\n", "line ten\n"));
+    let id = fixture.commit("Move both, editing one");
+
+    let records = run_records(&["--find-renames", "--attribute-renames", fixture.path()]);
+
+    assert_eq!(change(&records, &id, "pure-moved.txt")["similarity"], 100);
+    let edited = change(&records, &id, "edited-moved.txt");
+    assert_eq!(edited["status"], "renamed");
+    let similarity = edited["similarity"].as_u64().unwrap();
+    assert!(similarity > 50 && similarity < 100, "{}", similarity);
+
+    let records = run_records(&["--find-renames", fixture.path()]);
+    assert!(change(&records, &id, "edited-moved.txt")["similarity"].is_null());
+}
+
+#[test]
+fn uncommitted_changes_staged_or_not_are_a_working_commit() {
+    let fixture = Fixture::new();
+    fixture.write("staged.txt", "one\n");
+    fixture.write("unstaged.txt", "one\n");
+    let head = fixture.commit("First");
+    fixture.write("staged.txt", "one\ntwo\n");
+    fixture.write("new.txt", "new\n");
+    fixture.git(&["add", "staged.txt", "new.txt"]);
+    fixture.write("unstaged.txt", "changed\n");
+    fixture.write("untracked.txt", "not added\n");
+
+    let records = run_records(&["--include-uncommitted", fixture.path()]);
+
+    let working: Vec<&Value> = records
+        .iter()
+        .take_while(|record| record["id"] == "WORKING")
+        .collect();
+    let paths: Vec<&str> = working
+        .iter()
+        .map(|record| record["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["new.txt", "staged.txt", "unstaged.txt"]);
+    for record in &working {
+        assert_eq!(record["short_id"], "WORKING");
+        assert_eq!(record["is_working"], true);
+        assert_eq!(record["tree_id"], Value::Null);
+    }
+    assert_eq!(change(&records, "WORKING", "new.txt")["status"], "added");
+    let staged = change(&records, "WORKING", "staged.txt");
+    assert_eq!(staged["lines_added"], 1);
+    assert_eq!(staged["lines_removed"], 0);
+    let unstaged = change(&records, "WORKING", "unstaged.txt");
+    assert_eq!(unstaged["lines_added"], 1);
+    assert_eq!(unstaged["lines_removed"], 1);
+
+    let real = &records[working.len()..];
+    assert_eq!(real.len(), 2);
+    assert!(real.iter().all(|record| record["id"] == head));
+    assert!(real.iter().all(|record| record["is_working"] == false));
+
+    // Without the flag, or with nothing uncommitted, there's no WORKING commit
+    //
+    let records = run_records(&[fixture.path()]);
+    assert!(records.iter().all(|record| record["id"] == head));
+    fixture.remove("untracked.txt");
+    fixture.commit("Second");
+    let records = run_records(&["--include-uncommitted", fixture.path()]);
+    assert!(records.iter().all(|record| record["is_working"] == false));
+}
+
+#[test]
+fn reindenting_a_file_is_a_whitespace_only_change() {
+    let fixture = Fixture::new();
+    let code = "fn main() {\nlet x = 1;\nprintln!(\"{}\", x);\n}\n";
+    fixture.write("reindented.rs", code);
+    fixture.write("edited.rs", code);
+    fixture.write("blank_lines.rs", code);
+    fixture.write("moved.rs", code);
+    let first = fixture.commit("First");
+    fixture.write(
+        "reindented.rs",
+        "fn main() {\n    let x = 1;\n\tprintln!(\"{}\",  x);\n}\n",
+    );
+    fixture.write(
+        "edited.rs",
+        "fn main() {\n    let x = 2;\nprintln!(\"{}\", x);\n}\n",
+    );
+    fixture.write("blank_lines.rs", format!("{}\n\n", code));
+    fixture.git(&["mv", "moved.rs", "renamed.rs"]);
+    let second = fixture.commit("Reformat");
+
+    let records = run_records(&["--find-renames", fixture.path()]);
+
+    let reindented = change(&records, &second, "reindented.rs");
+    assert_eq!(reindented["whitespace_only"], true);
+    assert_eq!(reindented["lines_removed"], 2);
+    assert_eq!(
+        change(&records, &second, "edited.rs")["whitespace_only"],
+        false
+    );
+    assert_eq!(
+        change(&records, &second, "blank_lines.rs")["whitespace_only"],
+        false
+    );
+    assert_eq!(
+        change(&records, &second, "renamed.rs")["whitespace_only"],
+        false
+    );
+    assert_eq!(
+        change(&records, &first, "moved.rs")["whitespace_only"],
+        false
+    );
+}
+
+#[test]
+fn modified_hunk_lines_tell_an_append_from_an_in_place_edit() {
+    let fixture = Fixture::new();
+    let lines: Vec<String> = (1..=20).map(|n| format!("{}\n", n)).collect();
+    fixture.write("appended.txt", lines.concat());
+    fixture.write("edited.txt", lines.concat());
+    fixture.commit("First");
+    fixture.write("appended.txt", format!("{}21\n22\n", lines.concat()));
+    // Far enough apart to be two hunks
+    //
+    let mut edited = lines.clone();
+    edited.splice(
+        14..15,
+        ["fourteen\n".to_string(), "and a half\n".to_string()],
+    );
+    edited.insert(3, "three and a bit\n".to_string());
+    fixture.write("edited.txt", edited.concat());
+    let id = fixture.commit("Append to one, edit the other");
+
+    let records = run_records(&["--with-modified-hunk-lines", fixture.path()]);
+
+    let appended = change(&records, &id, "appended.txt");
+    assert_eq!(appended["lines_added"], 2);
+    assert_eq!(appended["hunks_added"], 1);
+    assert_eq!(appended["additions_in_modified_hunks"], 0);
+    assert_eq!(appended["deletions_in_modified_hunks"], 0);
+
+    // One hunk adding a line outright, and one replacing 15 with two lines
+    //
+    let edited = change(&records, &id, "edited.txt");
+    assert_eq!(edited["lines_added"], 3);
+    assert_eq!(edited["lines_removed"], 1);
+    assert_eq!(edited["hunks_added"], 1);
+    assert_eq!(edited["hunks_modified"], 1);
+    assert_eq!(edited["additions_in_modified_hunks"], 2);
+    assert_eq!(edited["deletions_in_modified_hunks"], 1);
+
+    let records = run_records(&[fixture.path()]);
+    assert!(change(&records, &id, "edited.txt")["additions_in_modified_hunks"].is_null());
+}
+
+#[test]
+fn missing_newlines_at_the_end_of_a_file_arent_counted_as_lines() {
+    let fixture = Fixture::new();
+    fixture.write("gains.txt", "a\nb");
+    fixture.write("drops.txt", "a\nb\n");
+    fixture.write("never.txt", "a\nb");
+    fixture.commit("First");
+    fixture.write("gains.txt", "a\nb\n");
+    fixture.write("drops.txt", "a\nb");
+    fixture.write("never.txt", "A\nb");
+    fixture.write("new.txt", "x\ny");
+    let id = fixture.commit("Change the final newlines");
+
+    let records = run_records(&[fixture.path()]);
+
+    // The same as git's own counts
+    //
+    let numstat = fixture.git(&["show", "--numstat", "--format=", &id]);
+    for line in numstat.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let row = change(&records, &id, fields[2]);
+        assert_eq!(row["lines_added"].to_string(), fields[0], "{}", line);
+        assert_eq!(row["lines_removed"].to_string(), fields[1], "{}", line);
+    }
+    assert_eq!(numstat.lines().count(), 4);
+
+    for (path, added, removed) in [
+        ("gains.txt", 1, 1),
+        ("drops.txt", 1, 1),
+        ("never.txt", 1, 1),
+        ("new.txt", 2, 0),
+    ] {
+        let row = change(&records, &id, path);
+        assert_eq!(row["lines_added"], added, "{}", path);
+        assert_eq!(row["lines_removed"], removed, "{}", path);
+    }
+}
+
+#[test]
+fn a_heavily_edited_move_is_only_a_rename_below_the_threshold() {
+    let fixture = Fixture::new();
+    let lines: Vec<String> = (1..=10)
+        .map(|n| format!("line {} of the original file\n", n))
+        .collect();
+    fixture.write("old.txt", lines.concat());
+    fixture.commit("First");
+    // Move it, rewriting most of it on the way
+    //
+    fixture.remove("old.txt");
+    let mut edited = lines.clone();
+    for line in edited.iter_mut().skip(4) {
+        *line = format!("something else entirely: {}", line.to_uppercase());
+    }
+    fixture.write("new.txt", edited.concat());
+    let id = fixture.commit("Move and rewrite");
+
+    let rows = |args: &[&str]| -> Vec<Value> {
+        run_records(
+            &[
+                &["--find-renames", "--attribute-renames"],
+                args,
+                &[fixture.path()],
+            ]
+            .concat(),
+        )
+        .into_iter()
+        .filter(|record| record["id"] == id)
+        .collect()
+    };
+
+    let renamed = rows(&["--rename-threshold", "20"]);
+    assert_eq!(renamed.len(), 1);
+    assert_eq!(renamed[0]["status"], "renamed");
+    assert_eq!(renamed[0]["old_path"], "old.txt");
+    assert_eq!(renamed[0]["path"], "new.txt");
+    let similarity = renamed[0]["similarity"].as_u64().unwrap();
+    assert!((20..50).contains(&similarity), "{}", similarity);
+
+    // Not similar enough at the default of 50, or just above its similarity
+    //
+    let above = (similarity + 1).to_string();
+    for args in [
+        &[][..],
+        &["--rename-threshold", "50"],
+        &["--rename-threshold", &above],
+    ] {
+        let split = rows(args);
+        let mut statuses: Vec<&str> = split
+            .iter()
+            .map(|row| row["status"].as_str().unwrap())
+            .collect();
+        statuses.sort();
+        assert_eq!(statuses, ["added", "deleted"], "{:?}", args);
+        assert!(split.iter().all(|row| row["old_path"].is_null()));
+    }
+
+    let output = common::run(&[
+        "--find-renames",
+        "--rename-threshold",
+        "101",
+        fixture.path(),
+    ]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,252 @@
+// Throwaway git repos to run the binary against, built with the git command line
+// so they're made the same way as anyone's real repos. Every commit is given a
+// fixed author, committer and date, so the ids (and anything else written) are
+// the same each time a test runs
+//
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::{
+    cell::Cell,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::Path,
+    process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+use tempfile::TempDir;
+
+// The date of a fixture's first commit - each commit after it is a minute later
+//
+const FIRST_COMMIT_TIME: i64 = 1_700_000_000;
+
+pub struct Fixture {
+    dir: TempDir,
+    commits: Cell<i64>,
+}
+
+impl Fixture {
+    pub fn new() -> Fixture {
+        let fixture = Fixture {
+            dir: TempDir::new().expect("tempdir"),
+            commits: Cell::new(0),
+        };
+        fixture.git(&["init", "--quiet", "--initial-branch=main"]);
+        fixture
+    }
+
+    pub fn path(&self) -> &str {
+        self.dir.path().to_str().expect("UTF-8 tempdir")
+    }
+
+    // Runs git in the fixture, panicking if it fails, and gives back its stdout
+    //
+    pub fn git(&self, args: &[&str]) -> String {
+        self.git_at(args, FIRST_COMMIT_TIME + self.commits.get() * 60)
+    }
+
+    // As git, with the author and committer dates both set to time
+    //
+    pub fn git_at(&self, args: &[&str], time: i64) -> String {
+        let date = format!("{} +0000", time);
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(self.dir.path())
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "Ada")
+            .env("GIT_AUTHOR_EMAIL", "ada@example.com")
+            .env("GIT_COMMITTER_NAME", "Ada")
+            .env("GIT_COMMITTER_EMAIL", "ada@example.com")
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
+            .output()
+            .expect("git runs");
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) {
+        let path = self.dir.path().join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("create dir");
+        }
+        std::fs::write(path, contents).expect("write file");
+    }
+
+    pub fn remove(&self, path: &str) {
+        std::fs::remove_file(self.dir.path().join(path)).expect("remove file");
+    }
+
+    // Commits everything in the worktree, giving back the new commit's id
+    //
+    pub fn commit(&self, message: &str) -> String {
+        self.git(&["add", "--all"]);
+        self.git(&["commit", "--quiet", "--allow-empty", "--message", message]);
+        self.commits.set(self.commits.get() + 1);
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    // Feeds a git fast-import stream into the fixture - quicker than committing one
+    // at a time for long histories, and the stream sets the dates itself
+    //
+    pub fn fast_import(&self, stream: &str) {
+        let mut import = Command::new("git")
+            .args(["fast-import", "--quiet"])
+            .current_dir(self.dir.path())
+            .stdin(Stdio::piped())
+            .spawn()
+            .expect("git runs");
+        import
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stream.as_bytes())
+            .expect("write to fast-import");
+        assert!(import.wait().unwrap().success(), "fast-import failed");
+    }
+
+    // Merges branch into the current one with a merge commit, giving back its id
+    //
+    pub fn merge(&self, branch: &str, message: &str) -> String {
+        self.git(&["merge", "--quiet", "--no-ff", "--message", message, branch]);
+        self.commits.set(self.commits.get() + 1);
+        self.git(&["rev-parse", "HEAD"])
+    }
+}
+
+// The binary, with no arguments yet and kept clear of the user's git config
+//
+pub fn command() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_git-fetch-commits"));
+    command
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1");
+    command
+}
+
+// Runs the binary, with progress kept off stderr so only errors end up there
+//
+pub fn run(args: &[&str]) -> Output {
+    command()
+        .args(["--progress-format", "json", "--progress-file", "/dev/null"])
+        .args(args)
+        .output()
+        .expect("git-fetch-commits runs")
+}
+
+// As run, failing the test unless the run succeeds
+//
+pub fn run_ok(args: &[&str]) -> Output {
+    let output = run(args);
+    assert!(
+        output.status.success(),
+        "git-fetch-commits {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+// The records in a run's ndjson output
+//
+pub fn records(output: &[u8]) -> Vec<Value> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("a JSON record per line"))
+        .collect()
+}
+
+// The ndjson records written by a successful run
+//
+pub fn run_records(args: &[&str]) -> Vec<Value> {
+    records(&run_ok(args).stdout)
+}
+
+pub fn read_records(path: &Path) -> Vec<Value> {
+    records(&std::fs::read(path).expect("read output"))
+}
+
+// An HTTP server turning down every request with a 401 asking for a username and
+// password, for as long as it's running. Stopping it gives back the Authorization
+// header of each request in turn (None for a request without one)
+//
+pub struct AuthServer {
+    pub url: String,
+    stop: Arc<AtomicBool>,
+    server: JoinHandle<Vec<Option<String>>>,
+}
+
+impl AuthServer {
+    pub fn start() -> AuthServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        listener.set_nonblocking(true).expect("nonblocking");
+        let url = format!("http://{}/repo.git", listener.local_addr().unwrap());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let stopping = stop.clone();
+        let server = std::thread::spawn(move || {
+            let mut authorizations = Vec::new();
+            while !stopping.load(Ordering::Relaxed) {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(_e) => {
+                        std::thread::sleep(Duration::from_millis(10));
+                        continue;
+                    }
+                };
+                stream.set_nonblocking(false).expect("blocking");
+
+                let mut authorization = None;
+                let mut reader = BufReader::new(&stream);
+                loop {
+                    let mut header = String::new();
+                    if reader.read_line(&mut header).unwrap_or(0) == 0 || header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("authorization") {
+                            authorization = Some(value.trim().to_string());
+                        }
+                    }
+                }
+                authorizations.push(authorization);
+
+                let _ = (&stream).write_all(
+                    b"HTTP/1.1 401 Unauthorized\r\n\
+                      WWW-Authenticate: Basic realm=\"test\"\r\n\
+                      Content-Length: 0\r\n\
+                      Connection: close\r\n\r\n",
+                );
+            }
+            authorizations
+        });
+
+        AuthServer { url, stop, server }
+    }
+
+    pub fn stop(self) -> Vec<Option<String>> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.server.join().expect("server thread")
+    }
+}
+
+// The Authorization header for HTTP basic auth with a username and password
+//
+pub fn basic_auth(username: &str, password: &str) -> String {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", username, password))
+    )
+}
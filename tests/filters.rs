@@ -0,0 +1,278 @@
+mod common;
+
+use common::{run_records, Fixture};
+
+// The subject of each commit written, in the order they were
+//
+fn subjects(args: &[&str]) -> Vec<String> {
+    run_records(&[&["--nested"], args].concat())
+        .iter()
+        .map(|record| record["message"].as_str().unwrap().trim().to_string())
+        .collect()
+}
+
+fn messages_repo() -> Fixture {
+    let fixture = Fixture::new();
+    for (file, message) in [
+        ("a.txt", "feat: add a"),
+        ("b.txt", "fix: mend b"),
+        ("c.txt", "Feat: add c"),
+        ("d.txt", "docs: explain d"),
+    ] {
+        fixture.write(file, "text\n");
+        fixture.commit(message);
+    }
+    fixture
+}
+
+#[test]
+fn grep_keeps_only_the_matching_commits() {
+    let fixture = messages_repo();
+
+    assert_eq!(
+        subjects(&["--grep", "^feat:", fixture.path()]),
+        ["feat: add a"]
+    );
+    assert_eq!(
+        subjects(&["--grep", "(?i)^feat:", fixture.path()]),
+        ["Feat: add c", "feat: add a"]
+    );
+}
+
+#[test]
+fn grep_invert_leaves_out_the_matching_commits() {
+    let fixture = messages_repo();
+
+    assert_eq!(
+        subjects(&["--grep", "(?i)^feat:", "--grep-invert", fixture.path()]),
+        ["docs: explain d", "fix: mend b"]
+    );
+}
+
+#[test]
+fn an_invalid_grep_pattern_fails_before_doing_anything() {
+    let output = common::run(&["--grep", "fix(", "/nonexistent/repo"]);
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--grep"), "{}", stderr);
+    assert!(!stderr.contains("Clone"), "{}", stderr);
+}
+
+#[test]
+fn type_picks_normal_commits_or_merges() {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "a\n");
+    fixture.commit("Start");
+    fixture.git(&["checkout", "--quiet", "-b", "feature"]);
+    fixture.write("b.txt", "b\n");
+    fixture.commit("Add b");
+    fixture.git(&["checkout", "--quiet", "main"]);
+    fixture.merge("feature", "Merge the feature");
+
+    assert_eq!(
+        subjects(&["--type", "merge", fixture.path()]),
+        ["Merge the feature"]
+    );
+    assert_eq!(
+        subjects(&["--type", "normal", fixture.path()]),
+        ["Add b", "Start"]
+    );
+    assert_eq!(
+        subjects(&["--type", "all", fixture.path()]),
+        subjects(&[fixture.path()])
+    );
+    assert_eq!(subjects(&[fixture.path()]).len(), 3);
+}
+
+#[test]
+fn chronological_writes_the_oldest_commit_first() {
+    let fixture = messages_repo();
+
+    let newest_first = subjects(&[fixture.path()]);
+    assert_eq!(
+        newest_first,
+        [
+            "docs: explain d",
+            "Feat: add c",
+            "fix: mend b",
+            "feat: add a"
+        ]
+    );
+
+    let mut oldest_first = subjects(&["--chronological", fixture.path()]);
+    oldest_first.reverse();
+    assert_eq!(oldest_first, newest_first);
+}
+
+#[test]
+fn max_path_depth_leaves_out_deeper_files() {
+    let fixture = Fixture::new();
+    for path in ["top.txt", "a/b/c/three.txt", "a/b/c/d/e/five.txt"] {
+        fixture.write(path, "text\n");
+    }
+    fixture.commit("Files at each depth");
+    fixture.write("a/b/c/d/e/five.txt", "changed\n");
+    fixture.commit("Only a deep change");
+
+    let paths = |args: &[&str]| -> Vec<String> {
+        run_records(&[args, &[fixture.path()]].concat())
+            .iter()
+            .map(|record| record["path"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(
+        paths(&[]),
+        [
+            "a/b/c/d/e/five.txt",
+            "a/b/c/d/e/five.txt",
+            "a/b/c/three.txt",
+            "top.txt"
+        ]
+    );
+    // The commit changing only the deep file is left with no rows
+    //
+    assert_eq!(
+        paths(&["--max-path-depth", "3"]),
+        ["a/b/c/three.txt", "top.txt"]
+    );
+    assert_eq!(paths(&["--max-path-depth", "0"]), ["top.txt"]);
+    assert_eq!(paths(&["--max-path-depth", "5"]).len(), 4);
+}
+
+#[test]
+fn stable_order_breaks_timestamp_ties_by_id() {
+    // Three branches off the root, all committed in the same second, and an
+    // octopus merge of them a minute later
+    //
+    let fixture = Fixture::new();
+    fixture.write("root.txt", "root\n");
+    let root = fixture.commit("Root");
+    let time = 1_700_000_600;
+    let mut tied = Vec::new();
+    for branch in ["one", "two", "three"] {
+        fixture.git(&["checkout", "--quiet", "-b", branch, &root]);
+        fixture.write(&format!("{}.txt", branch), "branch\n");
+        fixture.git(&["add", "--all"]);
+        fixture.git_at(&["commit", "--quiet", "--message", branch], time);
+        tied.push(fixture.git(&["rev-parse", "HEAD"]));
+    }
+    fixture.git(&["checkout", "--quiet", "main"]);
+    fixture.git_at(
+        &[
+            "merge",
+            "--quiet",
+            "--message",
+            "Octopus",
+            "one",
+            "two",
+            "three",
+        ],
+        time + 60,
+    );
+    let merge = fixture.git(&["rev-parse", "HEAD"]);
+    tied.sort();
+
+    let ids = |args: &[&str]| -> Vec<String> {
+        run_records(&[&["--no-diff", "--stable-order"], args, &[fixture.path()]].concat())
+            .iter()
+            .map(|record| record["id"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let newest_first = ids(&[]);
+    let mut expected = [vec![merge], tied, vec![root]].concat();
+    assert_eq!(newest_first, expected);
+    assert_eq!(ids(&[]), newest_first);
+    // Oldest first, the tied commits are still in ascending id order
+    //
+    expected.reverse();
+    expected[1..4].sort();
+    assert_eq!(ids(&["--chronological"]), expected);
+
+    let output = common::run(&["--stable-order", "--streaming", fixture.path()]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn ext_keeps_only_files_with_the_listed_extensions() {
+    let fixture = Fixture::new();
+    for path in [
+        "src/main.rs",
+        "src/LIB.RS",
+        "Cargo.toml",
+        "README.md",
+        "Makefile",
+    ] {
+        fixture.write(path, "text\n");
+    }
+    fixture.commit("Start");
+    fixture.write("README.md", "changed\n");
+    fixture.commit("Only docs");
+
+    let paths = |ext: &str| -> Vec<String> {
+        run_records(&["--ext", ext, fixture.path()])
+            .iter()
+            .map(|record| record["path"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(paths("rs"), ["src/LIB.RS", "src/main.rs"]);
+    assert_eq!(
+        paths("rs,toml"),
+        ["Cargo.toml", "src/LIB.RS", "src/main.rs"]
+    );
+    assert_eq!(paths("md,"), ["README.md", "Makefile", "README.md"]);
+}
+
+#[test]
+fn after_and_before_commit_bound_the_walk_between_two_tags() {
+    let fixture = Fixture::new();
+    let mut ids = Vec::new();
+    for n in 0..5 {
+        fixture.write("counter.txt", format!("{}\n", n));
+        ids.push(fixture.commit(&format!("Commit {}", n)));
+    }
+    fixture.git(&["tag", "v1", &ids[1]]);
+    fixture.git(&["tag", "-a", "v2", "-m", "Release 2", &ids[3]]);
+    // A branch off v1 that --all-branches takes in too
+    //
+    fixture.git(&["checkout", "--quiet", "-b", "side", "v1"]);
+    fixture.write("side.txt", "side\n");
+    let side = fixture.commit("On the side");
+    fixture.git(&["checkout", "--quiet", "main"]);
+
+    let walked = |args: &[&str]| -> Vec<String> {
+        run_records(&[&["--no-diff"], args, &[fixture.path()]].concat())
+            .iter()
+            .map(|record| record["id"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(
+        walked(&["--after-commit", "v1", "--before-commit", "v2"]),
+        [ids[3].clone(), ids[2].clone()]
+    );
+    assert_eq!(walked(&["--after-commit", "v2"]), [ids[4].clone()]);
+    assert_eq!(
+        walked(&["--before-commit", "v1"]),
+        [ids[1].clone(), ids[0].clone()]
+    );
+    assert_eq!(
+        walked(&["--before-commit", "side", "--after-commit", "HEAD~4"]),
+        [side.clone(), ids[1].clone()]
+    );
+    let mut all_after = walked(&["--all-branches", "--after-commit", "v2"]);
+    all_after.sort();
+    let mut expected = vec![ids[4].clone(), side];
+    expected.sort();
+    assert_eq!(all_after, expected);
+
+    for args in [
+        &["--after-commit", "v9"][..],
+        &["--before-commit", "no-such-branch"],
+        &["--before-commit", "v2", "--all-branches"],
+    ] {
+        let output = common::run(&[args, &[fixture.path()]].concat());
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(output.stdout.is_empty(), "{:?}", args);
+    }
+}
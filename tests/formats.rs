@@ -0,0 +1,241 @@
+mod common;
+
+use common::{run_ok, run_records, Fixture};
+use serde_json::Value;
+
+fn two_commit_repo() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "one\n");
+    fixture.commit("First");
+    fixture.write("a.txt", "one\ntwo\n");
+    fixture.write("b.txt", "new\n");
+    fixture.commit("Second");
+    fixture
+}
+
+fn stdout(args: &[&str]) -> String {
+    String::from_utf8(run_ok(args).stdout).expect("UTF-8 output")
+}
+
+#[test]
+fn pretty_prints_each_commit_over_several_lines() {
+    let fixture = two_commit_repo();
+
+    let output = stdout(&["--nested", "--pretty", fixture.path()]);
+
+    // Each commit starts a line of its own, with its fields indented below it
+    //
+    let starts: Vec<&str> = output.lines().filter(|line| *line == "{").collect();
+    assert_eq!(starts.len(), 2);
+    assert!(output.contains("\n  \"id\": "));
+    let commits: Vec<Value> = serde_json::Deserializer::from_str(&output)
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(commits.len(), 2);
+    assert_eq!(commits[0]["changes"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn pretty_array_is_an_indented_document() {
+    let fixture = two_commit_repo();
+
+    let output = stdout(&["--nested", "--pretty", "--array", fixture.path()]);
+
+    let commits: Vec<Value> = serde_json::from_str(&output).unwrap();
+    assert_eq!(commits.len(), 2);
+    assert!(output.starts_with("[\n"));
+    assert!(output.contains("\n    \"id\": "));
+}
+
+#[test]
+fn pretty_needs_nested() {
+    let fixture = two_commit_repo();
+
+    let output = common::run(&["--pretty", fixture.path()]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn emit_commit_row_gives_a_merge_a_row() {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "a\n");
+    fixture.commit("Start");
+    fixture.git(&["checkout", "--quiet", "-b", "feature"]);
+    fixture.write("b.txt", "b\n");
+    fixture.commit("Add b");
+    fixture.git(&["checkout", "--quiet", "main"]);
+    let merge = fixture.merge("feature", "Merge the feature");
+    let empty = fixture.commit("Nothing at all");
+
+    let records = run_records(&[fixture.path()]);
+    assert_eq!(records.len(), 2);
+
+    let records = run_records(&["--emit-commit-row", fixture.path()]);
+    assert_eq!(records.len(), 4);
+    for id in [&empty, &merge] {
+        let rows: Vec<&Value> = records
+            .iter()
+            .filter(|record| record["id"] == *id)
+            .collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["path"], "");
+        assert_eq!(rows[0]["status"], "unmodified");
+    }
+
+    let output = common::run(&["--emit-commit-row", "--format", "postgres", fixture.path()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--emit-commit-row"));
+}
+
+#[test]
+fn split_by_branch_writes_each_commit_into_every_branch_it_is_on() {
+    let fixture = Fixture::new();
+    fixture.write("shared.txt", "shared\n");
+    let shared = fixture.commit("Shared");
+    fixture.git(&["checkout", "--quiet", "-b", "team/feature"]);
+    fixture.write("feature.txt", "feature\n");
+    let feature = fixture.commit("Add a feature");
+    fixture.git(&["checkout", "--quiet", "main"]);
+    fixture.write("main.txt", "main\n");
+    let main = fixture.commit("Carry on on main");
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+
+    run_ok(&[
+        "--all-branches",
+        "--with-branches",
+        "--split-by",
+        "branch",
+        "--output-dir",
+        out.to_str().unwrap(),
+        fixture.path(),
+    ]);
+
+    let mut files: Vec<String> = std::fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["main.ndjson", "team%2Ffeature.ndjson"]);
+
+    let ids = |file: &str| -> Vec<String> {
+        common::read_records(&out.join(file))
+            .iter()
+            .map(|record| record["id"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(ids("main.ndjson"), [main, shared.clone()]);
+    assert_eq!(ids("team%2Ffeature.ndjson"), [feature, shared]);
+}
+
+#[test]
+fn split_by_branch_rejects_csv_before_writing_anything() {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "a\n");
+    fixture.commit("First");
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+
+    let output = common::run(&[
+        "--with-branches",
+        "--split-by",
+        "branch",
+        "--format",
+        "csv",
+        "--output-dir",
+        out.to_str().unwrap(),
+        fixture.path(),
+    ]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--split-by only works with the ndjson, msgpack and avro formats"));
+    assert!(!out.exists());
+}
+
+#[test]
+fn rotate_bytes_rolls_over_between_commits() {
+    let fixture = Fixture::new();
+    for n in 0..6 {
+        fixture.write(&format!("{}-a.txt", n), "a\n");
+        fixture.write(&format!("{}-b.txt", n), "b\n");
+        fixture.commit(&format!("Commit {}", n));
+    }
+    let dir = tempfile::tempdir().unwrap();
+
+    for (format, extension) in [("ndjson", "ndjson"), ("csv", "csv")] {
+        let path = dir.path().join(format!("out.{}", extension));
+        run_ok(&[
+            "--format",
+            format,
+            "--output-file",
+            path.to_str().unwrap(),
+            "--rotate-bytes",
+            "1B",
+            fixture.path(),
+        ]);
+
+        // Each commit (two rows) goes over the size, so every file holds one commit
+        //
+        for n in 1..=6 {
+            let file = dir.path().join(format!("out.{:05}.{}", n, extension));
+            let contents = std::fs::read_to_string(&file).unwrap();
+            assert!(contents.ends_with('\n'), "{:?}", file);
+            match format {
+                "ndjson" => {
+                    let records = common::records(contents.as_bytes());
+                    assert_eq!(records.len(), 2, "{:?}", file);
+                    assert_eq!(records[0]["id"], records[1]["id"]);
+                }
+                _ => {
+                    let mut reader = csv::Reader::from_reader(contents.as_bytes());
+                    assert!(reader
+                        .headers()
+                        .unwrap()
+                        .iter()
+                        .any(|header| header == "path"));
+                    assert_eq!(reader.records().count(), 2, "{:?}", file);
+                }
+            }
+        }
+        assert!(!dir.path().join(format!("out.00007.{}", extension)).exists());
+    }
+}
+
+#[test]
+fn long_nested_paths_come_through_whole() {
+    let fixture = Fixture::new();
+    // 300 characters over ten directories, ending in a name that CSV has to quote
+    //
+    let dirs: Vec<String> = (0..10)
+        .map(|n| format!("directory-{:02}-{}", n, "d".repeat(12)))
+        .collect();
+    let mut path = dirs.join("/");
+    path.push('/');
+    let name = "a file, \"quoted\".txt";
+    path.push_str(&"n".repeat(300 - path.len() - name.len()));
+    path.push_str(name);
+    assert_eq!(path.len(), 300);
+    fixture.write(&path, "deep\n");
+    fixture.commit("Add a deeply nested file");
+
+    let records = run_records(&[fixture.path()]);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["path"], path);
+
+    let nested = run_records(&["--nested", fixture.path()]);
+    assert_eq!(nested[0]["changes"][0]["path"], path);
+
+    let csv = stdout(&["--format", "csv", fixture.path()]);
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    let column = reader
+        .headers()
+        .unwrap()
+        .iter()
+        .position(|header| header == "path")
+        .unwrap();
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(&rows[0][column], path);
+}
@@ -0,0 +1,90 @@
+// Produces to the broker at $KAFKA_BROKERS, so it's only built with the kafka
+// feature and only run when asked for:
+//
+//   KAFKA_BROKERS=localhost:9092 cargo test --features kafka --test kafka -- --ignored
+//
+#![cfg(feature = "kafka")]
+
+mod common;
+
+use common::{run_ok, Fixture};
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{BaseConsumer, Consumer},
+    Message,
+};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+// Reads every message on topic until count have arrived, as (key, payload)
+//
+fn consume(brokers: &str, topic: &str, count: usize) -> Vec<(String, Value)> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", topic)
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .unwrap();
+    consumer.subscribe(&[topic]).unwrap();
+
+    let started = Instant::now();
+    let mut messages = Vec::new();
+    while messages.len() < count {
+        assert!(
+            started.elapsed() < Duration::from_secs(60),
+            "{:?}",
+            messages
+        );
+        if let Some(message) = consumer.poll(Duration::from_millis(500)) {
+            let message = message.unwrap();
+            messages.push((
+                String::from_utf8_lossy(message.key().unwrap()).to_string(),
+                serde_json::from_slice(message.payload().unwrap()).unwrap(),
+            ));
+        }
+    }
+    messages
+}
+
+#[test]
+#[ignore = "needs a Kafka broker at $KAFKA_BROKERS"]
+fn kafka_gets_a_message_per_row_keyed_by_commit() {
+    let brokers = std::env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS");
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "a\n");
+    fixture.write("b.txt", "b\n");
+    let first = fixture.commit("Add two files");
+    fixture.write("a.txt", "changed\n");
+    let second = fixture.commit("Change one");
+    let topic = format!(
+        "git-fetch-commits-test-{}",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    );
+
+    run_ok(&[
+        "--format",
+        "kafka",
+        "--kafka-brokers",
+        &brokers,
+        "--kafka-topic",
+        &topic,
+        fixture.path(),
+    ]);
+
+    let mut messages = consume(&brokers, &topic, 3);
+    messages.sort_by(|a, b| (&a.0, a.1["path"].as_str()).cmp(&(&b.0, b.1["path"].as_str())));
+    let mut expected = vec![
+        (first.clone(), "a.txt"),
+        (first, "b.txt"),
+        (second, "a.txt"),
+    ];
+    expected.sort();
+    for ((key, record), (id, path)) in messages.iter().zip(&expected) {
+        assert_eq!(key, id);
+        assert_eq!(record["id"], *id);
+        assert_eq!(record["path"], *path);
+    }
+}
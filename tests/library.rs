@@ -0,0 +1,173 @@
+mod common;
+
+use common::Fixture;
+use git_fetch_commits::{
+    error::Error,
+    extract::run_with,
+    settings::{DateSource, Settings},
+};
+
+fn repo_with_commits(count: usize) -> Fixture {
+    let fixture = Fixture::new();
+    for n in 0..count {
+        fixture.write("counter.txt", format!("{}\n", n));
+        fixture.commit(&format!("Commit {}", n));
+    }
+    fixture
+}
+
+#[test]
+fn run_with_calls_back_for_every_commit() {
+    let first = repo_with_commits(3);
+    let second = repo_with_commits(2);
+    let repo_urls = vec![first.path().to_string(), second.path().to_string()];
+
+    let mut seen = Vec::new();
+    let outcomes = run_with(
+        &Settings::default(),
+        &repo_urls,
+        &Default::default(),
+        |commit| {
+            seen.push((commit.repo_url.clone(), commit.changes.len()));
+            Ok(())
+        },
+    )
+    .unwrap();
+
+    assert_eq!(seen.len(), 5);
+    assert!(seen.iter().all(|(_, changes)| *changes == 1));
+    assert_eq!(seen[0].0, first.path());
+    assert_eq!(seen[4].0, second.path());
+
+    let commits: Vec<usize> = outcomes
+        .iter()
+        .map(|(_, outcome)| outcome.as_ref().unwrap().commits)
+        .collect();
+    assert_eq!(commits, [3, 2]);
+}
+
+#[test]
+fn an_error_from_the_callback_fails_the_repo() {
+    let first = repo_with_commits(3);
+    let second = repo_with_commits(2);
+    let repo_urls = vec![first.path().to_string(), second.path().to_string()];
+    let settings = Settings {
+        continue_on_error: true,
+        ..Settings::default()
+    };
+
+    let mut calls = 0;
+    let outcomes = run_with(&settings, &repo_urls, &Default::default(), |commit| {
+        calls += 1;
+        if commit.repo_url == first.path() {
+            return Err(Error::Config("not this one".to_string()));
+        }
+        Ok(())
+    })
+    .unwrap();
+
+    // The first repo stops at its first commit, and the second is still done
+    //
+    assert_eq!(calls, 3);
+    assert!(matches!(&outcomes[0].1, Err(Error::Config(message)) if message == "not this one"));
+    assert_eq!(outcomes[1].1.as_ref().unwrap().commits, 2);
+}
+
+#[test]
+fn an_error_from_the_callback_stops_the_run_by_default() {
+    let first = repo_with_commits(1);
+    let second = repo_with_commits(1);
+    let repo_urls = vec![first.path().to_string(), second.path().to_string()];
+
+    let outcomes = run_with(
+        &Settings::default(),
+        &repo_urls,
+        &Default::default(),
+        |_commit| Err(Error::Config("stop".to_string())),
+    )
+    .unwrap();
+
+    assert_eq!(outcomes.len(), 1);
+    assert!(outcomes[0].1.is_err());
+}
+
+// Library users get the same timestamps as the command line unless they ask for
+// something else - the author time
+//
+#[test]
+fn timestamps_are_the_author_time_by_default() {
+    let fixture = repo_with_commits(1);
+    fixture.git_at(
+        &[
+            "commit",
+            "--quiet",
+            "--amend",
+            "--no-edit",
+            "--date",
+            "1600000000 +0000",
+        ],
+        1_700_000_000 + 3600,
+    );
+    let repo_urls = vec![fixture.path().to_string()];
+
+    let mut timestamps = Vec::new();
+    run_with(
+        &Settings::default(),
+        &repo_urls,
+        &Default::default(),
+        |commit| {
+            timestamps.push(commit.timestamp);
+            Ok(())
+        },
+    )
+    .unwrap();
+
+    assert!(matches!(
+        Settings::default().date_source,
+        DateSource::Author
+    ));
+    assert_eq!(timestamps, [1_600_000_000]);
+}
+
+#[test]
+fn an_error_from_the_callback_is_the_repo_outcome_with_jobs() {
+    let first = repo_with_commits(3);
+    let second = repo_with_commits(2);
+    let repo_urls = vec![first.path().to_string(), second.path().to_string()];
+    let settings = Settings {
+        jobs: 2,
+        continue_on_error: true,
+        ..Settings::default()
+    };
+
+    let mut written = 0;
+    let outcomes = run_with(&settings, &repo_urls, &Default::default(), |commit| {
+        if commit.repo_url == first.path() {
+            return Err(Error::Config("not this one".to_string()));
+        }
+        written += 1;
+        Ok(())
+    })
+    .unwrap();
+
+    assert!(matches!(&outcomes[0].1, Err(Error::Config(message)) if message == "not this one"));
+    assert_eq!(outcomes[1].1.as_ref().unwrap().commits, 2);
+    assert_eq!(written, 2);
+}
+
+#[test]
+fn an_error_from_the_callback_stops_the_run_with_jobs() {
+    let repo = repo_with_commits(20);
+    let repo_urls = vec![repo.path().to_string()];
+    let settings = Settings {
+        jobs: 2,
+        ..Settings::default()
+    };
+
+    let outcomes = run_with(&settings, &repo_urls, &Default::default(), |_commit| {
+        Err(Error::Config("stop".to_string()))
+    })
+    .unwrap();
+
+    assert!(matches!(&outcomes[0].1, Err(Error::Config(message)) if message == "stop"));
+}
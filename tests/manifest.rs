@@ -0,0 +1,82 @@
+mod common;
+
+use common::{run, Fixture};
+use git_fetch_commits::SCHEMA_VERSION;
+use serde_json::Value;
+
+fn read_manifest(path: &std::path::Path) -> Value {
+    serde_json::from_slice(&std::fs::read(path).expect("manifest written")).unwrap()
+}
+
+#[test]
+fn manifest_records_what_was_asked_for_and_how_it_went() {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "a\n");
+    fixture.commit("feat: add a");
+    fixture.write("b.txt", "b\n");
+    fixture.commit("fix: mend b");
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = dir.path().join("manifest.json");
+    let manifest_path = manifest.to_str().unwrap();
+
+    let output = run(&[
+        "--manifest",
+        manifest_path,
+        "--format",
+        "csv",
+        "--grep",
+        "^feat:",
+        "--max-path-depth",
+        "2",
+        fixture.path(),
+    ]);
+    assert!(output.status.success());
+
+    let written = read_manifest(&manifest);
+    assert_eq!(written["tool_version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(written["schema_version"], SCHEMA_VERSION);
+    let started_at = written["started_at"].as_i64().unwrap();
+    assert!(started_at > 1_700_000_000);
+    assert!(written["finished_at"].as_i64().unwrap() >= started_at);
+    assert_eq!(written["format"], "csv");
+    assert_eq!(written["nested"], false);
+    assert_eq!(written["filters"]["grep"], "^feat:");
+    assert_eq!(written["filters"]["max_path_depth"], 2);
+    assert_eq!(written["filters"]["all_branches"], false);
+    assert_eq!(
+        written["repos"],
+        serde_json::json!([{
+            "repo_url": fixture.path(),
+            "status": "ok",
+            "commits": 1,
+            "clone_bytes": null,
+        }])
+    );
+    assert_eq!(written["commits"], 1);
+    assert!(written["error"].is_null());
+    assert_eq!(written["exit_status"], 0);
+
+    // A failed run gets one too, credentials left out
+    //
+    let output = run(&[
+        "--manifest",
+        manifest_path,
+        "--continue-on-error",
+        "-P",
+        "hunter2",
+        fixture.path(),
+        "/nonexistent/repo",
+    ]);
+    assert!(!output.status.success());
+
+    let written = read_manifest(&manifest);
+    assert_eq!(written["repos"][0]["status"], "ok");
+    assert_eq!(written["repos"][1]["repo_url"], "/nonexistent/repo");
+    assert_eq!(written["repos"][1]["status"], "failed");
+    assert!(written["repos"][1]["error"].is_string());
+    assert_eq!(written["commits"], 2);
+    assert_eq!(written["exit_status"], 1);
+    assert!(!std::fs::read_to_string(&manifest)
+        .unwrap()
+        .contains("hunter2"));
+}
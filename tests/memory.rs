@@ -0,0 +1,83 @@
+// Peak memory is read from /proc, so this only runs on Linux
+//
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::{command, Fixture};
+use std::{process::Stdio, time::Duration};
+
+// A history that many commits long, each one changing a single small file
+//
+fn long_history(commits: u64) -> Fixture {
+    let fixture = Fixture::new();
+    let mut stream = String::new();
+    for n in 0..commits {
+        let message = format!("Commit {}", n);
+        let contents = format!("{}\n", n);
+        stream.push_str(&format!(
+            "commit refs/heads/main\n\
+             committer Ada <ada@example.com> {} +0000\n\
+             data {}\n{}\n\
+             M 644 inline counter.txt\n\
+             data {}\n{}\n",
+            1_700_000_000 + n,
+            message.len(),
+            message,
+            contents.len(),
+            contents
+        ));
+    }
+    fixture.fast_import(&stream);
+    fixture
+}
+
+// Runs the binary, giving back the most memory it had resident at any point (in
+// KiB). VmHWM only ever goes up, so the last reading before it exits is as good as
+// the peak
+//
+fn peak_memory(args: &[&str]) -> u64 {
+    let mut child = command()
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let status = format!("/proc/{}/status", child.id());
+    let mut peak = 0;
+    loop {
+        if let Ok(contents) = std::fs::read_to_string(&status) {
+            if let Some(kib) = contents
+                .lines()
+                .find_map(|line| line.strip_prefix("VmHWM:"))
+                .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+            {
+                peak = kib;
+            }
+        }
+        if let Some(exit) = child.try_wait().unwrap() {
+            assert!(exit.success());
+            return peak;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn streaming_holds_less_of_a_long_history_in_memory() {
+    let fixture = long_history(50_000);
+
+    let default = peak_memory(&["--no-diff", fixture.path()]);
+    let streaming = peak_memory(&["--no-diff", "--streaming", fixture.path()]);
+
+    // About 100MB against 40MB when measured - the object cache and the list of
+    // ids are most of the difference
+    //
+    assert!(streaming > 0);
+    assert!(
+        streaming * 3 < default * 2,
+        "streaming peaked at {}KiB, the default at {}KiB",
+        streaming,
+        default
+    );
+}
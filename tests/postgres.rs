@@ -0,0 +1,88 @@
+// Writes to the database at $POSTGRES_CONFIG (key=value form, e.g. "host=localhost
+// user=postgres"), so it's only built with the postgres feature and only run when
+// asked for:
+//
+//   POSTGRES_CONFIG="host=localhost user=postgres" cargo test --features postgres --test postgres -- --ignored
+//
+// Each test's tables go in a schema of their own, dropped afterwards
+//
+#![cfg(feature = "postgres")]
+
+mod common;
+
+use common::{run_ok, Fixture};
+use postgres::{Client, NoTls};
+
+// A new schema, and the config that writes into it
+//
+fn schema(client: &mut Client) -> (String, String) {
+    let config = std::env::var("POSTGRES_CONFIG").expect("POSTGRES_CONFIG");
+    let schema = format!(
+        "git_fetch_commits_{}",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    );
+    client
+        .batch_execute(&format!("CREATE SCHEMA {}", schema))
+        .unwrap();
+    let config = format!("{} options='-c search_path={}'", config, schema);
+    (schema, config)
+}
+
+fn count(client: &mut Client, schema: &str, table: &str) -> i64 {
+    client
+        .query_one(&format!("SELECT count(*) FROM {}.{}", schema, table), &[])
+        .unwrap()
+        .get(0)
+}
+
+#[test]
+#[ignore = "needs a PostgreSQL database at $POSTGRES_CONFIG"]
+fn commits_and_changes_are_written_once() {
+    let mut client = Client::connect(&std::env::var("POSTGRES_CONFIG").unwrap(), NoTls).unwrap();
+    let (schema, config) = schema(&mut client);
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "a\n");
+    fixture.write("b.txt", "b\n");
+    fixture.commit("Add two files");
+    fixture.write("a.txt", "changed\n");
+    let second = fixture.commit("Change one");
+
+    let args = [
+        "--format",
+        "postgres",
+        "--postgres",
+        &config,
+        "--create-tables",
+        fixture.path(),
+    ];
+    run_ok(&args);
+    assert_eq!(count(&mut client, &schema, "commits"), 2);
+    assert_eq!(count(&mut client, &schema, "file_changes"), 3);
+
+    // Running again adds nothing
+    //
+    run_ok(&args);
+    assert_eq!(count(&mut client, &schema, "commits"), 2);
+    assert_eq!(count(&mut client, &schema, "file_changes"), 3);
+
+    let row = client
+        .query_one(
+            &format!(
+                "SELECT c.message, f.path, f.lines_added FROM {0}.commits c \
+                 JOIN {0}.file_changes f ON f.commit_id = c.id WHERE c.id = $1",
+                schema
+            ),
+            &[&second],
+        )
+        .unwrap();
+    assert_eq!(row.get::<_, String>(0), "Change one\n");
+    assert_eq!(row.get::<_, String>(1), "a.txt");
+    assert_eq!(row.get::<_, i64>(2), 1);
+
+    client
+        .batch_execute(&format!("DROP SCHEMA {} CASCADE", schema))
+        .unwrap();
+}
@@ -0,0 +1,195 @@
+mod common;
+
+use common::{command, run, Fixture};
+use serde_json::Value;
+
+fn one_commit_repo() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "hello\n");
+    fixture.commit("First");
+    fixture
+}
+
+#[test]
+fn invalid_progress_template_is_a_startup_error() {
+    let fixture = one_commit_repo();
+
+    let output = run(&["--progress-template", "{bar:x}", fixture.path()]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("Invalid --progress-template"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"));
+}
+
+#[test]
+fn json_progress_is_written_as_events_to_stderr() {
+    let fixture = one_commit_repo();
+    // A file:// URL, as a plain path is cloned by copying the objects directly
+    // with no transfer to report
+    //
+    let url = format!("file://{}", fixture.path());
+
+    let output = command()
+        .args(["--progress-format", "json", &url])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let events: Vec<Value> = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let last = events.last().expect("at least one progress event");
+    assert_eq!(last["repo_url"], url);
+    assert_eq!(last["phase"], "indexing");
+    assert_eq!(last["indexed_objects"], last["total_objects"]);
+    for event in &events {
+        for field in [
+            "received_objects",
+            "total_objects",
+            "indexed_objects",
+            "bytes",
+        ] {
+            assert!(event[field].is_u64(), "{}", event);
+        }
+    }
+}
+
+#[test]
+fn progress_file_keeps_the_bars_off_stderr() {
+    let fixture = one_commit_repo();
+    let url = format!("file://{}", fixture.path());
+    let progress = tempfile::NamedTempFile::new().unwrap();
+
+    let output = command()
+        .args(["--progress-file", progress.path().to_str().unwrap(), &url])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    // Only the summary is left on stderr
+    //
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Summary:\n"), "{}", stderr);
+    assert_eq!(stderr.lines().count(), 2);
+    let bars = std::fs::read_to_string(progress.path()).unwrap();
+    assert!(bars.contains("Cloning..."), "{}", bars);
+}
+
+#[test]
+fn diff_bar_counts_up_to_the_commits_walked() {
+    let fixture = one_commit_repo();
+    for n in 2..=5 {
+        fixture.write("a.txt", format!("{}\n", n));
+        fixture.commit(&format!("Commit {}", n));
+    }
+    let short_ids = fixture.git(&["log", "--abbrev=7", "--format=%h"]);
+    let progress = tempfile::NamedTempFile::new().unwrap();
+
+    for streaming in [&[][..], &["--streaming"]] {
+        std::fs::write(progress.path(), "").unwrap();
+        let output = command()
+            .args(["--progress-file", progress.path().to_str().unwrap()])
+            .args(["--progress-template", "{pos}/{len} {msg}"])
+            .args(streaming)
+            .arg(fixture.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let bars = std::fs::read_to_string(progress.path()).unwrap();
+        assert_eq!(bars.lines().last(), Some("5/5 Diff complete"), "{}", bars);
+        // Redraws are rate limited, so not every commit's short id need be shown -
+        // but the bar never gets ahead of the commits
+        //
+        for line in bars.lines().filter(|line| line.contains("/5 ")) {
+            let position: u64 = line.split('/').next().unwrap().parse().unwrap();
+            assert!(position <= 5, "{}", line);
+            let message = line.split_once(' ').unwrap().1;
+            assert!(
+                ["Diffing...", "Diff complete"].contains(&message)
+                    || short_ids.lines().any(|short_id| short_id == message),
+                "{}",
+                line
+            );
+        }
+    }
+}
+
+#[test]
+fn color_never_keeps_ansi_escapes_out_of_the_bars() {
+    let fixture = one_commit_repo();
+    let progress = tempfile::NamedTempFile::new().unwrap();
+
+    let escapes = |color: &str| {
+        std::fs::write(progress.path(), "").unwrap();
+        let output = command()
+            .args(["--color", color])
+            .args(["--progress-file", progress.path().to_str().unwrap()])
+            .args([fixture.path(), "/nonexistent/repo"])
+            .output()
+            .unwrap();
+        assert!(!output.stderr.contains(&0x1b), "{:?}", output.stderr);
+        let bars = std::fs::read(progress.path()).unwrap();
+        assert!(!bars.is_empty());
+        bars.contains(&0x1b)
+    };
+    assert!(!escapes("never"));
+    // A file isn't a terminal
+    //
+    assert!(!escapes("auto"));
+    assert!(escapes("always"));
+}
+
+#[test]
+fn summary_gives_the_bytes_each_clone_transferred() {
+    let fixture = one_commit_repo();
+    let url = format!("file://{}", fixture.path());
+    let manifest = tempfile::NamedTempFile::new().unwrap();
+
+    let output = run(&[
+        "--manifest",
+        manifest.path().to_str().unwrap(),
+        &url,
+        fixture.path(),
+    ]);
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let bytes = |repo: &str| -> u64 {
+        let line = stderr
+            .lines()
+            .find(|line| line.starts_with(&format!("  {} => ok, 1 commits, ", repo)))
+            .unwrap_or_else(|| panic!("no summary line for {} in {}", repo, stderr));
+        line.split(", ")
+            .nth(2)
+            .unwrap()
+            .strip_suffix(" bytes cloned")
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    let transferred = bytes(&url);
+    assert!(transferred > 0);
+    // Cloned by copying the objects rather than transferring them
+    //
+    assert!(
+        stderr.contains(&format!(
+            "  {} => ok, 1 commits, copied locally\n",
+            fixture.path()
+        )),
+        "{}",
+        stderr
+    );
+
+    let manifest: Value = serde_json::from_slice(&std::fs::read(manifest.path()).unwrap()).unwrap();
+    assert_eq!(manifest["repos"][0]["clone_bytes"], transferred);
+    assert_eq!(manifest["repos"][1]["clone_bytes"], Value::Null);
+}
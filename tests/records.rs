@@ -0,0 +1,593 @@
+mod common;
+
+use common::{run, run_records, Fixture};
+use git_fetch_commits::SCHEMA_VERSION;
+
+fn two_commit_repo() -> Fixture {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "one\n");
+    fixture.commit("First");
+    fixture.write("a.txt", "one\ntwo\n");
+    fixture.write("b.txt", "new\n");
+    fixture.commit("Second");
+    fixture
+}
+
+#[test]
+fn every_record_carries_the_schema_version() {
+    let fixture = two_commit_repo();
+    // Something uncommitted, for the WORKING record
+    //
+    fixture.write("a.txt", "changed\n");
+
+    let path = fixture.path();
+    for args in [
+        &["--include-uncommitted", path][..],
+        &["--include-uncommitted", "--nested", path],
+        &["--no-diff", path],
+    ] {
+        let records = run_records(args);
+        assert!(!records.is_empty());
+        for record in records {
+            assert_eq!(record["schema_version"], SCHEMA_VERSION, "{}", record);
+        }
+    }
+}
+
+#[test]
+fn error_records_carry_the_schema_version() {
+    let fixture = two_commit_repo();
+    let output = run(&[
+        "--emit-errors",
+        "--continue-on-error",
+        fixture.path(),
+        "/nonexistent/repo",
+    ]);
+
+    let records = common::records(&output.stdout);
+    assert_eq!(records.last().unwrap()["record_kind"], "error");
+    for record in records {
+        assert_eq!(record["schema_version"], SCHEMA_VERSION, "{}", record);
+    }
+}
+
+#[test]
+fn output_is_the_same_every_run() {
+    let fixture = Fixture::new();
+    for path in ["zebra.txt", "apple.txt", "dir/mango.txt", "Banana.txt"] {
+        fixture.write(path, "fruit\n");
+    }
+    fixture.commit("Add fruit");
+    fixture.write("apple.txt", "changed\n");
+    fixture.write("zebra.txt", "changed\n");
+    fixture.commit("Change fruit");
+
+    let first = common::run_ok(&[fixture.path()]).stdout;
+    let second = common::run_ok(&[fixture.path()]).stdout;
+    assert_eq!(first, second);
+
+    let paths: Vec<String> = common::records(&first)
+        .iter()
+        .map(|record| record["path"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            "apple.txt",
+            "zebra.txt",
+            "Banana.txt",
+            "apple.txt",
+            "dir/mango.txt",
+            "zebra.txt"
+        ]
+    );
+}
+
+#[test]
+fn short_id_is_a_prefix_of_the_id() {
+    let fixture = two_commit_repo();
+
+    for (args, length) in [(&[][..], 7), (&["--abbrev", "12"], 12)] {
+        let records = run_records(&[args, &[fixture.path()]].concat());
+        for record in records {
+            let id = record["id"].as_str().unwrap();
+            let short_id = record["short_id"].as_str().unwrap();
+            assert_eq!(short_id.len(), length);
+            assert!(id.starts_with(short_id));
+        }
+    }
+}
+
+#[test]
+fn a_revert_shares_its_tree_id() {
+    let fixture = two_commit_repo();
+    let second = fixture.git(&["rev-parse", "HEAD"]);
+    fixture.write("a.txt", "three\n");
+    let third = fixture.commit("Third");
+    fixture.git(&["revert", "--no-edit", &third]);
+    let revert = fixture.git(&["rev-parse", "HEAD"]);
+
+    let records = run_records(&["--no-diff", fixture.path()]);
+
+    let tree_id = |id: &str| {
+        let record = records.iter().find(|record| record["id"] == id).unwrap();
+        record["tree_id"].as_str().unwrap().to_string()
+    };
+    assert_eq!(tree_id(&revert), tree_id(&second));
+    assert_ne!(tree_id(&third), tree_id(&second));
+    assert_eq!(tree_id(&second), fixture.git(&["rev-parse", "HEAD^{tree}"]));
+}
+
+#[test]
+fn merges_are_flagged_with_their_parent_count() {
+    let fixture = two_commit_repo();
+    fixture.git(&["checkout", "--quiet", "-b", "feature", "HEAD~1"]);
+    fixture.write("feature.txt", "feature\n");
+    fixture.commit("Add a feature");
+    fixture.git(&["checkout", "--quiet", "main"]);
+    let merge = fixture.merge("feature", "Merge the feature");
+
+    let records = run_records(&["--no-diff", fixture.path()]);
+
+    let merge = records.iter().find(|record| record["id"] == merge).unwrap();
+    assert_eq!(merge["is_merge"], true);
+    assert_eq!(merge["parent_count"], 2);
+    assert_eq!(merge["type"], "Merge");
+
+    let first = records.last().unwrap();
+    assert_eq!(first["is_merge"], false);
+    assert_eq!(first["parent_count"], 0);
+    assert_eq!(first["type"], "Normal");
+}
+
+#[test]
+fn only_the_first_commit_is_a_root() {
+    let fixture = two_commit_repo();
+    fixture.write("a.txt", "three\n");
+    fixture.commit("Third");
+
+    let records = run_records(&["--no-diff", fixture.path()]);
+
+    let roots: Vec<bool> = records
+        .iter()
+        .map(|record| record["is_root"].as_bool().unwrap())
+        .collect();
+    assert_eq!(roots, [false, false, true]);
+    assert_eq!(records[2]["parent_count"], 0);
+}
+
+#[test]
+fn unrelated_roots_each_add_their_whole_tree() {
+    let fixture = two_commit_repo();
+    fixture.git(&["checkout", "--quiet", "--orphan", "other"]);
+    fixture.git(&["rm", "--quiet", "-r", "--cached", "."]);
+    fixture.remove("a.txt");
+    fixture.remove("b.txt");
+    fixture.write("c.txt", "c\n");
+    fixture.write("dir/d.txt", "d\n");
+    let other_root = fixture.commit("Another history");
+    fixture.git(&["checkout", "--quiet", "main"]);
+    fixture.git(&[
+        "merge",
+        "--quiet",
+        "--allow-unrelated-histories",
+        "--message",
+        "Join the histories",
+        "other",
+    ]);
+    let first_root = fixture.git(&["rev-list", "--max-parents=0", "main"]);
+    let first_root = first_root
+        .lines()
+        .find(|id| *id != other_root)
+        .unwrap()
+        .to_string();
+
+    let records = run_records(&[fixture.path()]);
+
+    let added = |id: &str| {
+        let rows: Vec<&serde_json::Value> =
+            records.iter().filter(|record| record["id"] == id).collect();
+        assert!(rows.iter().all(|row| row["is_root"] == true));
+        assert!(rows.iter().all(|row| row["status"] == "added"));
+        let mut paths: Vec<&str> = rows
+            .iter()
+            .map(|row| row["path"].as_str().unwrap())
+            .collect();
+        paths.sort();
+        paths
+    };
+    assert_eq!(added(&first_root), ["a.txt"]);
+    assert_eq!(added(&other_root), ["c.txt", "dir/d.txt"]);
+    assert_eq!(
+        records
+            .iter()
+            .filter(|record| record["is_root"] == true)
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn timestamp_is_the_author_time_unless_asked_for_the_committer_time() {
+    let fixture = two_commit_repo();
+    // Amend the last commit as if it was written a while before it was committed
+    //
+    let committed = 1_700_000_000 + 3600;
+    fixture.git_at(
+        &[
+            "commit",
+            "--quiet",
+            "--amend",
+            "--no-edit",
+            "--date",
+            "1600000000 +0000",
+        ],
+        committed,
+    );
+
+    let timestamp = |args: &[&str]| {
+        let records = run_records(&[args, &["--no-diff", fixture.path()]].concat());
+        records[0]["timestamp"].as_i64().unwrap()
+    };
+    assert_eq!(timestamp(&[]), 1_600_000_000);
+    assert_eq!(timestamp(&["--date-source", "author"]), 1_600_000_000);
+    assert_eq!(timestamp(&["--date-source", "committer"]), committed);
+}
+
+#[test]
+fn sequence_counts_up_a_commit_at_a_time() {
+    let fixture = two_commit_repo();
+    fixture.write("a.txt", "three\n");
+    fixture.commit("Third");
+
+    for args in [
+        &["--with-sequence"][..],
+        &["--with-sequence", "--chronological"],
+    ] {
+        let records = run_records(&[args, &[fixture.path()]].concat());
+        let mut commits: Vec<&str> = records
+            .iter()
+            .map(|record| record["id"].as_str().unwrap())
+            .collect();
+        commits.dedup();
+        assert_eq!(commits.len(), 3);
+        // The second commit has two rows, which share its seq
+        //
+        for record in &records {
+            let position = commits.iter().position(|id| record["id"] == *id).unwrap();
+            assert_eq!(record["seq"], position, "{:?}", args);
+        }
+        assert_eq!(
+            records,
+            run_records(&[args, &[fixture.path()]].concat()),
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn abbrev_is_lengthened_where_it_would_be_ambiguous() {
+    // Enough commits (and blobs and trees) that plenty of 4 character prefixes are
+    // shared - made in one go with fast-import, at fixed times so the ids are
+    // always the same
+    //
+    let fixture = Fixture::new();
+    let mut stream = String::new();
+    for n in 0..2000 {
+        let message = format!("Commit {}", n);
+        let contents = format!("{}\n", n);
+        stream.push_str(&format!(
+            "commit refs/heads/main\n\
+             committer Ada <ada@example.com> {} +0000\n\
+             data {}\n{}\n\
+             M 644 inline counter.txt\n\
+             data {}\n{}\n",
+            1_700_000_000 + n,
+            message.len(),
+            message,
+            contents.len(),
+            contents
+        ));
+    }
+    fixture.fast_import(&stream);
+
+    let records = run_records(&["--no-diff", "--abbrev", "4", fixture.path()]);
+
+    let short_ids: Vec<&str> = records
+        .iter()
+        .map(|record| record["short_id"].as_str().unwrap())
+        .collect();
+    let git_short_ids = fixture.git(&["log", "--abbrev=4", "--format=%h"]);
+    assert_eq!(short_ids, git_short_ids.lines().collect::<Vec<_>>());
+    assert!(short_ids.iter().all(|short_id| short_id.len() >= 4));
+
+    let lengthened = short_ids
+        .iter()
+        .filter(|short_id| short_id.len() > 4)
+        .count();
+    assert!(lengthened > 0);
+    let mut unique = short_ids.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), short_ids.len());
+}
+
+#[test]
+fn record_hashes_are_the_same_every_run_and_differ_between_rows() {
+    let fixture = two_commit_repo();
+
+    let hashes = |args: &[&str]| -> Vec<String> {
+        run_records(&[&["--with-record-hash"], args, &[fixture.path()]].concat())
+            .iter()
+            .map(|record| record["record_hash"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let flat = hashes(&[]);
+    assert_eq!(flat.len(), 3);
+    assert_eq!(flat, hashes(&[]));
+    assert_eq!(flat, hashes(&["--with-sequence"]));
+    let mut unique = flat.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), 3);
+
+    let nested = hashes(&["--nested"]);
+    assert_eq!(nested.len(), 2);
+    assert!(nested.iter().all(|hash| !flat.contains(hash)));
+
+    let records = run_records(&[fixture.path()]);
+    assert!(records.iter().all(|record| record["record_hash"].is_null()));
+}
+
+#[test]
+fn author_and_committer_timezones_are_each_their_own() {
+    let fixture = two_commit_repo();
+    // Written in India, applied in California
+    //
+    let amended = std::process::Command::new("git")
+        .args(["commit", "--quiet", "--amend", "--no-edit"])
+        .args(["--date", "1700000000 +0530"])
+        .current_dir(fixture.path())
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_COMMITTER_DATE", "1700003600 -0800")
+        .env("GIT_COMMITTER_NAME", "Ada")
+        .env("GIT_COMMITTER_EMAIL", "ada@example.com")
+        .status()
+        .unwrap();
+    assert!(amended.success());
+
+    for args in [&["--no-diff"][..], &["--nested"], &[]] {
+        let records = run_records(&[args, &[fixture.path()]].concat());
+        assert_eq!(records[0]["author_tz_offset_minutes"], 330, "{:?}", args);
+        assert_eq!(
+            records[0]["committer_tz_offset_minutes"], -480,
+            "{:?}",
+            args
+        );
+        let first = records.last().unwrap();
+        assert_eq!(first["author_tz_offset_minutes"], 0);
+        assert_eq!(first["committer_tz_offset_minutes"], 0);
+    }
+}
+
+#[test]
+fn with_encoding_gives_the_encoding_a_commit_names() {
+    let fixture = two_commit_repo();
+    fixture.write("a.txt", "three\n");
+    fixture.git(&["add", "a.txt"]);
+    // "Café" in Latin-1, kept out of the worktree
+    //
+    fixture.write(".git/MESSAGE", b"Caf\xe9\n");
+    fixture.git(&[
+        "-c",
+        "i18n.commitEncoding=ISO-8859-1",
+        "commit",
+        "--quiet",
+        "--file",
+        ".git/MESSAGE",
+    ]);
+
+    let records = run_records(&["--with-encoding", "--no-diff", fixture.path()]);
+    assert_eq!(records[0]["encoding"], "ISO-8859-1");
+    assert_eq!(
+        records[0]["message"].as_str().unwrap().trim_end(),
+        "Caf\u{fffd}"
+    );
+    assert!(records[1]["encoding"].is_null());
+
+    let records = run_records(&["--no-diff", fixture.path()]);
+    assert!(records.iter().all(|record| record["encoding"].is_null()));
+}
+
+#[test]
+fn missing_author_fields_are_handled_as_asked_for_each() {
+    // Only fast-import will make commits without an email, or a name
+    //
+    let fixture = Fixture::new();
+    let mut stream = String::new();
+    for (n, author) in ["Ada <ada@example.com>", "Ada <>", "<bob@example.com>"]
+        .iter()
+        .enumerate()
+    {
+        stream.push_str(&format!(
+            "commit refs/heads/main\n\
+             author {author} {time} +0000\n\
+             committer Ada <ada@example.com> {time} +0000\n\
+             data 8\nCommit {n}\n\
+             M 644 inline file{n}.txt\n\
+             data 2\n{n}\n",
+            author = author,
+            time = 1_700_000_000 + n,
+            n = n
+        ));
+    }
+    fixture.fast_import(&stream);
+
+    // (name, email) of each commit written, newest first
+    //
+    let authors = |args: &[&str]| -> Vec<(serde_json::Value, serde_json::Value)> {
+        run_records(&[args, &["--no-diff", fixture.path()]].concat())
+            .iter()
+            .map(|record| {
+                (
+                    record["author_name"].clone(),
+                    record["author_email"].clone(),
+                )
+            })
+            .collect()
+    };
+    let ada = ("Ada".into(), "ada@example.com".into());
+
+    assert_eq!(
+        authors(&[]),
+        [
+            ("unknown".into(), "bob@example.com".into()),
+            ("Ada".into(), "unknown".into()),
+            ada.clone()
+        ]
+    );
+    assert_eq!(
+        authors(&["--on-missing-email", "placeholder"]),
+        authors(&[])
+    );
+    assert_eq!(
+        authors(&["--on-missing-email", "null"]),
+        [
+            ("unknown".into(), "bob@example.com".into()),
+            ("Ada".into(), serde_json::Value::Null),
+            ada.clone()
+        ]
+    );
+    assert_eq!(
+        authors(&["--on-missing-email", "skip"]),
+        [("unknown".into(), "bob@example.com".into()), ada.clone()]
+    );
+    assert_eq!(
+        authors(&["--on-missing-name", "null", "--on-missing-email", "skip"]),
+        [
+            (serde_json::Value::Null, "bob@example.com".into()),
+            ada.clone()
+        ]
+    );
+    assert_eq!(
+        authors(&["--on-missing-name", "skip", "--on-missing-email", "skip"]),
+        [ada]
+    );
+}
+
+#[test]
+fn commits_that_cant_be_diffed_are_skipped_and_recorded_as_errors() {
+    let fixture = Fixture::new();
+    fixture.write("a.txt", "one\n");
+    let first = fixture.commit("First");
+    fixture.write("a.txt", "two\n");
+    let second = fixture.commit("Second");
+    fixture.write("a.txt", "three\n");
+    let third = fixture.commit("Third");
+    fixture.write("b.txt", "unrelated\n");
+    let fourth = fixture.commit("Fourth");
+
+    // Lose the blob only the second commit has, so it and the third (which
+    // diffs against it) can't be diffed - the checkout doesn't need it
+    //
+    let blob = fixture.git(&["rev-parse", "HEAD~2:a.txt"]);
+    std::fs::remove_file(
+        std::path::Path::new(fixture.path())
+            .join(".git/objects")
+            .join(&blob[..2])
+            .join(&blob[2..]),
+    )
+    .unwrap();
+
+    let output = run(&[
+        "--emit-errors",
+        "--continue-on-error",
+        fixture.path(),
+        "/nonexistent/repo",
+    ]);
+    assert!(!output.status.success());
+
+    let records = common::records(&output.stdout);
+    let kinds: Vec<(&str, &str)> = records
+        .iter()
+        .map(|record| {
+            (
+                record["record_kind"].as_str().unwrap(),
+                record["id"].as_str().unwrap_or("-"),
+            )
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            ("commit", fourth.as_str()),
+            ("commit", first.as_str()),
+            ("error", third.as_str()),
+            ("error", second.as_str()),
+            ("error", "-"),
+        ]
+    );
+    for record in &records[2..4] {
+        assert_eq!(record["kind"], "diff");
+        assert_eq!(record["repo_url"], fixture.path());
+        assert!(
+            record["error"].as_str().unwrap().contains(&blob[..7]),
+            "{}",
+            record
+        );
+    }
+    assert_eq!(records[4]["repo_url"], "/nonexistent/repo");
+    assert!(records[4]["id"].is_null());
+    assert_eq!(records[4]["kind"], "clone");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("Skipping commit {}", second)),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("ok, 2 commits, copied locally, 2 commits skipped"),
+        "{}",
+        stderr
+    );
+
+    // Without --continue-on-error the first one fails the repo
+    //
+    let output = run(&["--emit-errors", fixture.path()]);
+    assert!(!output.status.success());
+    let records = common::records(&output.stdout);
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["id"], fourth);
+    assert_eq!(records[1]["record_kind"], "error");
+    assert!(records[1]["id"].is_null());
+}
+
+#[test]
+fn detect_squash_flags_a_squash_merged_pull_request() {
+    let fixture = two_commit_repo();
+    fixture.write("c.txt", "c\n");
+    fixture.commit("Add the c file (#42)\n\n* Add c\n\n* Fix c's contents\n\nCo-authored-by: Bo <bo@example.com>");
+
+    let records = run_records(&["--detect-squash", "--no-diff", fixture.path()]);
+    assert_eq!(records[0]["squash"], true);
+    assert_eq!(records[0]["pr_number"], 42);
+    assert_eq!(records[1]["squash"], false);
+    assert!(records[1]["pr_number"].is_null());
+
+    let records = run_records(&[
+        "--detect-squash",
+        "--squash-pattern",
+        r"^Add",
+        "--no-diff",
+        fixture.path(),
+    ]);
+    assert_eq!(records[0]["squash"], true);
+    assert!(records[0]["pr_number"].is_null());
+
+    let records = run_records(&["--no-diff", fixture.path()]);
+    assert!(records[0]["squash"].is_null());
+    assert!(records[0]["pr_number"].is_null());
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{run_records, Fixture};
+use serde_json::json;
+
+#[test]
+fn language_report_adds_up_churn_by_language() {
+    let fixture = Fixture::new();
+    fixture.write("src/main.rs", "fn main() {}\n");
+    fixture.write("config.yml", "a: 1\nb: 2\nc: 3\n");
+    fixture.write("notes", "no language\n");
+    fixture.commit("Start");
+    fixture.write("src/main.rs", "fn main() {\n    run();\n}\n");
+    fixture.write("src/lib.rs", "pub fn run() {}\n");
+    fixture.commit("Split out a library");
+
+    let report = run_records(&["--report", "languages", fixture.path()]);
+
+    // main.rs went 1 => 3 lines (1 removed, 3 added), lib.rs 1 added
+    //
+    assert_eq!(
+        report,
+        [
+            json!({"language": "Rust", "commits": 2, "files_changed": 3,
+                   "lines_added": 5, "lines_removed": 1, "churn": 6}),
+            json!({"language": "YAML", "commits": 1, "files_changed": 1,
+                   "lines_added": 3, "lines_removed": 0, "churn": 3}),
+            json!({"language": null, "commits": 1, "files_changed": 1,
+                   "lines_added": 1, "lines_removed": 0, "churn": 1}),
+        ]
+    );
+
+    let by_name = run_records(&[
+        "--report",
+        "languages",
+        "--report-sort",
+        "name",
+        "--report-order",
+        "asc",
+        fixture.path(),
+    ]);
+    let names: Vec<&serde_json::Value> = by_name.iter().map(|line| &line["language"]).collect();
+    assert_eq!(names, [&json!(null), &json!("Rust"), &json!("YAML")]);
+}
+
+#[test]
+fn report_lines_are_ranked_by_the_key_and_order_asked_for() {
+    let fixture = Fixture::new();
+    fixture.write("src/main.rs", "fn main() {}\n");
+    fixture.write("tool.py", "x = 1\n".repeat(10));
+    fixture.write("config.yml", "a: 1\nb: 2\nc: 3\nd: 4\ne: 5\nf: 6\n");
+    fixture.commit("Start");
+    fixture.write("src/main.rs", "fn main() {\n}\n");
+    fixture.write("config.yml", "g: 7\n");
+    fixture.commit("Trim the config");
+    fixture.write("src/lib.rs", "pub fn run() {}\n");
+    fixture.commit("Add a library");
+
+    // Rust: 3 commits, 4 added, 1 removed. Python: 1 commit, 10 added. YAML: 2
+    // commits, 7 added, 6 removed
+    //
+    let ranked = |args: &[&str]| -> Vec<String> {
+        run_records(&[&["--report", "languages"], args, &[fixture.path()]].concat())
+            .iter()
+            .map(|line| line["language"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(ranked(&[]), ["YAML", "Python", "Rust"]);
+    assert_eq!(
+        ranked(&["--report-order", "asc"]),
+        ["Rust", "Python", "YAML"]
+    );
+    assert_eq!(
+        ranked(&["--report-sort", "commits"]),
+        ["Rust", "YAML", "Python"]
+    );
+    assert_eq!(
+        ranked(&["--report-sort", "added"]),
+        ["Python", "YAML", "Rust"]
+    );
+    assert_eq!(
+        ranked(&["--report-sort", "removed"]),
+        ["YAML", "Rust", "Python"]
+    );
+    assert_eq!(
+        ranked(&["--report-sort", "removed", "--report-order", "asc"]),
+        ["Python", "Rust", "YAML"]
+    );
+
+    let output = common::run(&[
+        "--report",
+        "languages",
+        "--report-sort",
+        "size",
+        fixture.path(),
+    ]);
+    assert!(!output.status.success());
+}
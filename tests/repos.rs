@@ -0,0 +1,358 @@
+mod common;
+
+use common::{run_records, Fixture};
+
+fn one_commit_repo(file: &str) -> Fixture {
+    let fixture = Fixture::new();
+    fixture.write(file, "hello\n");
+    fixture.commit("Add a file");
+    fixture
+}
+
+#[test]
+fn repos_file_urls_are_processed_after_positional_ones() {
+    let first = one_commit_repo("first.txt");
+    let second = one_commit_repo("second.txt");
+    let third = one_commit_repo("third.txt");
+
+    let list = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(
+        list.path(),
+        format!(
+            "# repos to fetch\n\n  {}  \n# /not/this/one\n{}\n",
+            second.path(),
+            third.path()
+        ),
+    )
+    .unwrap();
+
+    let records = run_records(&["--repos-file", list.path().to_str().unwrap(), first.path()]);
+
+    let paths: Vec<&str> = records
+        .iter()
+        .map(|record| record["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["first.txt", "second.txt", "third.txt"]);
+    assert_eq!(records[1]["repo_url"], second.path());
+}
+
+#[test]
+fn unreadable_repos_file_fails_the_run() {
+    let output = common::run(&["--repos-file", "/nonexistent/repos.txt"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unable to read repos file"));
+}
+
+// A main branch and a feature branch, each with a commit the other doesn't have -
+// their ids are given back (main's first)
+//
+fn two_branch_repo() -> (Fixture, String, String) {
+    let fixture = one_commit_repo("shared.txt");
+    fixture.git(&["checkout", "--quiet", "-b", "feature"]);
+    fixture.write("feature.txt", "feature\n");
+    let feature = fixture.commit("Add a feature");
+    fixture.git(&["checkout", "--quiet", "main"]);
+    fixture.write("main.txt", "main\n");
+    let main = fixture.commit("Carry on on main");
+    (fixture, main, feature)
+}
+
+fn ids(records: &[serde_json::Value]) -> Vec<&str> {
+    records
+        .iter()
+        .map(|record| record["id"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn mirror_walks_every_branch_tip() {
+    let (fixture, main, feature) = two_branch_repo();
+
+    let records = run_records(&["--mirror", fixture.path()]);
+
+    let ids = ids(&records);
+    assert!(ids.contains(&main.as_str()));
+    assert!(ids.contains(&feature.as_str()));
+    assert_eq!(records.len(), 3);
+}
+
+#[test]
+fn only_the_default_branch_is_walked_by_default() {
+    let (fixture, main, feature) = two_branch_repo();
+
+    let records = run_records(&[fixture.path()]);
+
+    let ids = ids(&records);
+    assert!(ids.contains(&main.as_str()));
+    assert!(!ids.contains(&feature.as_str()));
+}
+
+#[test]
+fn all_branches_walks_the_other_branches_too() {
+    let (fixture, main, feature) = two_branch_repo();
+
+    let records = run_records(&["--all-branches", fixture.path()]);
+
+    let ids = ids(&records);
+    assert!(ids.contains(&main.as_str()));
+    assert!(ids.contains(&feature.as_str()));
+}
+
+#[test]
+fn all_branches_writes_commits_on_several_branches_once() {
+    let (fixture, _main, feature) = two_branch_repo();
+    fixture.git(&["branch", "feature-copy", "feature"]);
+    fixture.git(&["checkout", "--quiet", "-b", "further", "feature"]);
+    fixture.write("further.txt", "further\n");
+    let further = fixture.commit("Go further");
+    fixture.git(&["checkout", "--quiet", "main"]);
+
+    let records = run_records(&["--all-branches", fixture.path()]);
+
+    let ids = ids(&records);
+    assert_eq!(ids.len(), 4);
+    assert_eq!(ids.iter().filter(|id| **id == feature).count(), 1);
+    assert!(ids.contains(&further.as_str()));
+}
+
+// git daemon serving the fixture, killed when dropped. It's run directly rather
+// than through git, which would leave it running when git itself is killed
+//
+struct GitDaemon(std::process::Child);
+
+impl Drop for GitDaemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn git_protocol_clones_anonymously() {
+    let fixture = one_commit_repo("served.txt");
+    let dir = std::path::Path::new(fixture.path());
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let exec_path = fixture.git(&["--exec-path"]);
+    let _daemon = GitDaemon(
+        std::process::Command::new(std::path::Path::new(&exec_path).join("git-daemon"))
+            .arg(format!("--base-path={}", dir.parent().unwrap().display()))
+            .args(["--export-all", "--reuseaddr", "--listen=127.0.0.1"])
+            .arg(format!("--port={}", port))
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .expect("git daemon runs"),
+    );
+    let started = std::time::Instant::now();
+    while std::net::TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(started.elapsed().as_secs() < 10, "git daemon didn't start");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    let url = format!(
+        "git://127.0.0.1:{}/{}",
+        port,
+        dir.file_name().unwrap().to_str().unwrap()
+    );
+    let records = run_records(&[&url]);
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["path"], "served.txt");
+    assert_eq!(records[0]["repo_url"], url);
+}
+
+#[test]
+fn a_shallow_repo_marks_where_its_history_is_cut_off() {
+    let fixture = one_commit_repo("first.txt");
+    fixture.write("second.txt", "second\n");
+    fixture.commit("Add a second file");
+    fixture.write("third.txt", "third\n");
+    let last = fixture.commit("Add a third file");
+
+    let shallow = tempfile::tempdir().unwrap();
+    let shallow_path = shallow.path().join("shallow");
+    let shallow_path = shallow_path.to_str().unwrap();
+    fixture.git(&[
+        "clone",
+        "--quiet",
+        "--depth",
+        "2",
+        &format!("file://{}", fixture.path()),
+        shallow_path,
+    ]);
+
+    let output = common::run_ok(&[shallow_path]);
+    let records = common::records(&output.stdout);
+
+    // The commit it's cut off at is diffed against an empty tree, like a root
+    //
+    let paths: Vec<&str> = records
+        .iter()
+        .map(|record| record["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["third.txt", "first.txt", "second.txt"]);
+    assert_eq!(records[0]["id"], last);
+    assert_eq!(records[0]["shallow"], false);
+    assert_eq!(records[1]["shallow"], true);
+    assert_eq!(records[2]["shallow"], true);
+    for record in &records {
+        assert_eq!(record["is_root"], false);
+    }
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is a shallow clone"));
+
+    // The full repo has a root, and nothing shallow
+    //
+    let records = run_records(&[fixture.path()]);
+    assert_eq!(records.len(), 3);
+    assert!(records.iter().all(|record| record["shallow"] == false));
+    assert_eq!(records[2]["is_root"], true);
+}
+
+#[test]
+fn jobs_write_every_repos_commits_exactly_once() {
+    let (first, first_main, first_feature) = two_branch_repo();
+    let second = one_commit_repo("second.txt");
+    second.write("more.txt", "more\n");
+    let second_last = second.commit("Add more");
+
+    let records = run_records(&["--jobs", "2", "--all-branches", first.path(), second.path()]);
+
+    let first_root = first.git(&["rev-list", "--max-parents=0", "HEAD"]);
+    let second_root = second.git(&["rev-list", "--max-parents=0", "HEAD"]);
+    let mut expected = [
+        first_main.as_str(),
+        first_feature.as_str(),
+        first_root.as_str(),
+        second_last.as_str(),
+        second_root.as_str(),
+    ];
+    expected.sort();
+    let mut ids = ids(&records);
+    ids.sort();
+    assert_eq!(ids, expected);
+
+    // Each repo's own commits are still newest first
+    //
+    let second_records: Vec<&serde_json::Value> = records
+        .iter()
+        .filter(|record| record["repo_url"] == second.path())
+        .collect();
+    assert_eq!(second_records[0]["id"], second_last);
+}
+
+#[test]
+fn records_written_from_many_workers_never_interleave() {
+    let fixture = Fixture::new();
+    // Messages long enough that a record takes more than one write to the pipe
+    //
+    let long_line = "x".repeat(100_000);
+    for i in 0..20 {
+        fixture.write(&format!("file{}.txt", i % 4), format!("{}\n", i));
+        fixture.commit(&format!("Commit {}\n\n{}", i, long_line));
+    }
+    let repos = vec![fixture.path(); 32];
+
+    let output = common::run_ok(&[&["--jobs", "8"], &repos[..]].concat());
+
+    let lines: Vec<&[u8]> = output.stdout.split(|byte| *byte == b'\n').collect();
+    assert_eq!(lines.last(), Some(&&b""[..]));
+    let lines = &lines[..lines.len() - 1];
+    assert_eq!(lines.len(), 32 * 20);
+    for line in lines {
+        let record: serde_json::Value = serde_json::from_slice(line).expect("a whole record");
+        assert!(record["message"]
+            .as_str()
+            .unwrap()
+            .trim_end()
+            .ends_with(&long_line));
+    }
+}
+
+#[test]
+fn repo_root_is_the_directory_of_a_local_repo() {
+    let fixture = one_commit_repo("file.txt");
+    let root = std::fs::canonicalize(fixture.path()).unwrap();
+
+    let records = run_records(&["--with-repo-root", fixture.path()]);
+    assert_eq!(records[0]["repo_root"], root.to_str().unwrap());
+
+    // Given as its .git dir, it's the same root
+    //
+    let git_dir = root.join(".git");
+    let records = run_records(&["--with-repo-root", git_dir.to_str().unwrap()]);
+    assert_eq!(records[0]["repo_root"], root.to_str().unwrap());
+
+    let records = run_records(&[fixture.path()]);
+    assert!(records[0]["repo_root"].is_null());
+}
+
+#[test]
+fn repo_label_replaces_the_repo_url_but_not_where_its_cloned_from() {
+    let fixture = one_commit_repo("mirrored.txt");
+    let mirror = format!("file://{}", fixture.path());
+    let label = "https://github.com/example/project.git";
+
+    let records = run_records(&["--repo-label", label, &mirror]);
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["path"], "mirrored.txt");
+    assert_eq!(records[0]["id"], fixture.git(&["rev-parse", "HEAD"]));
+    assert_eq!(records[0]["repo_url"], label);
+
+    let records = run_records(&[&mirror]);
+    assert_eq!(records[0]["repo_url"], mirror);
+
+    // There's nothing to say which repo the one label would belong to
+    //
+    let second = one_commit_repo("second.txt");
+    let output = common::run(&["--repo-label", label, &mirror, second.path()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("single repo"));
+}
+
+#[test]
+fn remote_walks_the_named_remote_instead_of_the_checkout() {
+    let origin = one_commit_repo("origin.txt");
+    let (upstream, upstream_main, upstream_feature) = two_branch_repo();
+    let checkout = one_commit_repo("checkout.txt");
+    for (name, remote) in [("origin", &origin), ("upstream", &upstream)] {
+        checkout.git(&["remote", "add", name, remote.path()]);
+        checkout.git(&["fetch", "--quiet", name]);
+        checkout.git(&["remote", "set-head", name, "--auto"]);
+    }
+
+    let records = run_records(&["--remote", "upstream", checkout.path()]);
+    let upstream_root = upstream.git(&["rev-list", "--max-parents=0", "HEAD"]);
+    assert_eq!(
+        ids(&records),
+        [upstream_main.as_str(), upstream_root.as_str()]
+    );
+    assert!(records
+        .iter()
+        .all(|record| record["repo_url"] == upstream.path()));
+
+    let records = run_records(&["--remote", "upstream", "--all-branches", checkout.path()]);
+    assert!(ids(&records).contains(&upstream_feature.as_str()));
+    assert_eq!(records.len(), 3);
+
+    let records = run_records(&["--remote", "origin", checkout.path()]);
+    assert_eq!(records[0]["path"], "origin.txt");
+    assert_eq!(records[0]["repo_url"], origin.path());
+
+    // Without it, the checkout's own history
+    //
+    let records = run_records(&[checkout.path()]);
+    assert_eq!(records[0]["path"], "checkout.txt");
+    assert_eq!(records[0]["repo_url"], checkout.path());
+
+    let output = common::run(&["--remote", "fork", checkout.path()]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no remote named fork"));
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{run_ok, Fixture};
+use std::path::Path;
+
+// Five commits of two or three files each, so commits have several rows
+//
+fn five_commit_repo() -> Fixture {
+    let fixture = Fixture::new();
+    for n in 1..=5 {
+        fixture.write(&format!("{}.txt", n), "new\n");
+        fixture.write("a.txt", format!("{}\n", n));
+        if n % 2 == 0 {
+            fixture.write("b.txt", format!("{}\n", n));
+        }
+        fixture.commit(&format!("Commit {}", n));
+    }
+    fixture
+}
+
+fn output_to(path: &Path, args: &[&str], fixture: &Fixture) -> Vec<u8> {
+    let path = path.to_str().unwrap();
+    run_ok(&[args, &["--output-file", path, fixture.path()]].concat());
+    std::fs::read(path).unwrap()
+}
+
+// Where each commit's rows end in ndjson output
+//
+fn commit_ends(output: &[u8]) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut offset = 0;
+    let mut last_id = None;
+    for line in output.split_inclusive(|b| *b == b'\n') {
+        let record: serde_json::Value = serde_json::from_slice(line).unwrap();
+        if last_id.is_some() && last_id.as_ref() != Some(&record["id"]) {
+            ends.push(offset);
+        }
+        last_id = Some(record["id"].clone());
+        offset += line.len();
+    }
+    ends.push(offset);
+    ends
+}
+
+// Cuts a complete run's output off in a few places an interrupted run could have
+// left it, and resumes from each - the result has to be the same as never having
+// been interrupted
+//
+fn check_resume(args: &[&str]) {
+    let fixture = five_commit_repo();
+    let dir = tempfile::tempdir().unwrap();
+    let complete = output_to(&dir.path().join("complete.ndjson"), args, &fixture);
+    let ends = commit_ends(&complete);
+    assert_eq!(ends.len(), 5);
+
+    let cuts = [
+        // Nothing written yet
+        0,
+        // After the first commit
+        ends[0],
+        // Part way through the first row of the third commit
+        ends[1] + 10,
+        // Part way through the last row of the third commit, after its others
+        ends[2] - 5,
+        // All of it
+        complete.len(),
+    ];
+    for cut in cuts {
+        let path = dir.path().join(format!("cut-{}.ndjson", cut));
+        std::fs::write(&path, &complete[..cut]).unwrap();
+
+        let resumed = output_to(&path, &[args, &["--resume"]].concat(), &fixture);
+        assert_eq!(
+            String::from_utf8_lossy(&resumed),
+            String::from_utf8_lossy(&complete),
+            "resumed after {} bytes",
+            cut
+        );
+    }
+}
+
+#[test]
+fn resuming_a_chronological_run_gives_the_same_output() {
+    check_resume(&["--chronological"]);
+}
+
+#[test]
+fn resuming_a_newest_first_run_gives_the_same_output() {
+    check_resume(&[]);
+}
+
+#[test]
+fn resuming_keeps_the_sequence_numbers() {
+    check_resume(&["--chronological", "--with-sequence"]);
+}
+
+#[test]
+fn resuming_needs_one_record_per_line() {
+    let fixture = five_commit_repo();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.json");
+
+    let output = common::run(&[
+        "--array",
+        "--resume",
+        "--output-file",
+        path.to_str().unwrap(),
+        fixture.path(),
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--resume only works with one record per line"));
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::run_ok;
+use serde_json::{json, Value};
+
+fn printed_schema(args: &[&str]) -> Value {
+    let output = run_ok(&[&["--print-schema"], args].concat());
+    serde_json::from_slice(&output.stdout).expect("the schema is JSON")
+}
+
+#[test]
+fn schema_describes_the_flat_rows() {
+    let schema = printed_schema(&[]);
+
+    assert_eq!(schema["title"], "FlatCommit");
+    let properties = &schema["properties"];
+    assert_eq!(properties["id"]["type"], "string");
+    assert_eq!(properties["timestamp"]["type"], "integer");
+    assert_eq!(properties["lines_added"]["type"], "integer");
+    assert_eq!(properties["binary"]["type"], "boolean");
+    assert_eq!(properties["old_path"]["type"], json!(["string", "null"]));
+    assert!(properties.get("changes").is_none());
+}
+
+#[test]
+fn nested_schema_describes_whole_commits() {
+    let schema = printed_schema(&["--nested"]);
+
+    assert_eq!(schema["title"], "Commit");
+    assert_eq!(schema["properties"]["changes"]["type"], "array");
+    let change = &schema["definitions"]["FileChange"]["properties"];
+    assert_eq!(change["path"]["type"], "string");
+    assert_eq!(change["lines_removed"]["type"], "integer");
+}
+
+#[test]
+fn schema_piped_into_something_that_stops_reading_isnt_an_error() {
+    let mut child = common::command()
+        .arg("--print-schema")
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}